@@ -0,0 +1,11 @@
+-- Coordinator log for transactions spanning several drivers, see `runtime_v2::coordinator`
+-- participants :: json list of driver infos
+-- state        :: preparing | committing | aborting | committed | aborted
+
+CREATE TABLE IF NOT EXISTS TransactionLog (
+    txn_id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    participants TEXT NOT NULL,
+    state TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
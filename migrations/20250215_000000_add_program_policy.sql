@@ -0,0 +1,4 @@
+-- Per program policy, decided when the program is submitted
+-- allow_bind :: program may bind paths under the caller's namespace
+
+ALTER TABLE Program ADD COLUMN allow_bind INTEGER NOT NULL DEFAULT 0;
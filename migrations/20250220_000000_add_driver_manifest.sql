@@ -0,0 +1,4 @@
+-- Manifest a driver is loaded with, stored as json
+-- manifest :: description, schemas, operations and requested capabilities, NULL when none was given
+
+ALTER TABLE Driver ADD COLUMN manifest TEXT;
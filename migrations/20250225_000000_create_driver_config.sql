@@ -0,0 +1,12 @@
+-- Configuration of drivers, set by admins through `SetDriverConfig` and read by drivers through
+-- the `config` interface
+-- value :: utf-8 text, or nonce || ciphertext when secret, see `runtime_v2::secrets`
+
+CREATE TABLE IF NOT EXISTS DriverConfig (
+    driver_name TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value BLOB NOT NULL,
+    secret BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (driver_name, key)
+);
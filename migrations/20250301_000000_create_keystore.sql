@@ -0,0 +1,31 @@
+-- Keys admins provision for drivers through `ProvisionDriverKey` and `RotateDriverKey`, every
+-- version of a key is kept and drivers sign with the latest one through the `keystore` interface
+-- private_key :: nonce || ciphertext, see `runtime_v2::secrets`
+
+CREATE TABLE IF NOT EXISTS DriverKeys (
+    driver_name TEXT NOT NULL,
+    key_name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    algorithm TEXT NOT NULL,
+    public_key BLOB NOT NULL,
+    private_key BLOB NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (driver_name, key_name, version)
+);
+
+-- Audit log of every signature made by a driver
+-- payload_hash :: hex encoded sha256 of the signed payload
+
+CREATE TABLE IF NOT EXISTS KeySignatures (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    driver_name TEXT NOT NULL,
+    driver_version TEXT NOT NULL,
+    key_name TEXT NOT NULL,
+    key_version INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    execution_id TEXT NOT NULL,
+    payload_hash TEXT NOT NULL,
+    signed_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS KeySignaturesByDriver ON KeySignatures (driver_name, id);
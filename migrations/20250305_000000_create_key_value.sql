@@ -0,0 +1,7 @@
+-- Keys of the platform storage when `[platform.storage]` uses the sqlite backend, written by
+-- drivers through the `storage` interface
+
+CREATE TABLE IF NOT EXISTS KeyValue (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,25 @@
+-- Settlements of the keys routed to a settlement integration, queued in the same transaction as
+-- the value they settle and delivered by the outbox worker
+-- status :: pending | in-flight | delivered | failed
+--   pending :: waiting for its attempt at next_attempt_at
+--   in-flight :: claimed by a worker until next_attempt_at, claimed again once that has passed
+--   delivered :: taken by the integration
+--   failed :: given up on after max_attempts
+-- signature :: reference of the delivery given by the integration, a transaction signature
+
+CREATE TABLE IF NOT EXISTS Outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    integration TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    signature TEXT,
+    error TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS OutboxDue ON Outbox (status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS OutboxByKey ON Outbox (key, id);
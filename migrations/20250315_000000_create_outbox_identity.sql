@@ -0,0 +1,7 @@
+-- Identity of the outbox kept in this database, a single row generated the first time the outbox
+-- is opened. Settlements are handed to their integration as `{id}:{settlement id}`, a key that
+-- stays unique when the settlement ids of another outbox start over from the same numbers
+
+CREATE TABLE IF NOT EXISTS OutboxIdentity (
+    id TEXT NOT NULL
+);
@@ -1,7 +1,10 @@
 #[allow(warnings)]
 mod bindings;
 
-use bindings::{component::units::storage, exports::component::units::driver};
+use bindings::{
+    component::units::{clock, config, crypto},
+    exports::component::units::driver,
+};
 
 struct Component;
 
@@ -24,12 +27,14 @@ impl driver::Guest for Component {
     fn bind(input: String, _existing: Option<String>) -> Result<String, driver::DriverError> {
         let age_proof: AgeProof = serde_json::from_str(&input)
             .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+        verify_issuer(&age_proof)?;
 
-        let age =
-            (2025 - age_proof.year) * 365 + age_proof.month as u16 * 30 + age_proof.day as u16;
-        let age_in_years = age / 365;
+        let today = civil_date(clock::now().seconds / 86_400);
+        let age = age_on(&age_proof, today).ok_or_else(|| {
+            driver::DriverError::InvalidInput("Date of birth is in the future".to_string())
+        })?;
 
-        serde_json::to_string(&AgeCredentials { age: age_in_years })
+        serde_json::to_string(&AgeCredentials { age })
             .map_err(|e| driver::DriverError::SystemError(e.to_string()))
     }
 }
@@ -45,6 +50,82 @@ struct AgeProof {
     day: u8,
     month: u8,
     year: u16,
+    /// Hex encoded ed25519 signature of the issuer over the rest of the proof.
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+impl AgeProof {
+    /// What the issuer signs, `{name}|{year}-{month}-{day}`.
+    fn message(&self) -> String {
+        format!(
+            "{}|{:04}-{:02}-{:02}",
+            self.name, self.year, self.month, self.day
+        )
+    }
+}
+
+/// Checks the issuer's signature on `proof` when an `issuer_public_key` is configured for the
+/// driver, proofs aren't signed otherwise.
+fn verify_issuer(proof: &AgeProof) -> Result<(), driver::DriverError> {
+    let Some(public_key) = config::get("issuer_public_key") else {
+        return Ok(());
+    };
+    let public_key = decode_hex(&public_key).ok_or_else(|| {
+        driver::DriverError::SystemError("issuer_public_key isn't hex encoded".to_string())
+    })?;
+    let signature = proof
+        .signature
+        .as_deref()
+        .and_then(decode_hex)
+        .ok_or_else(|| driver::DriverError::InvalidInput("Proof isn't signed".to_string()))?;
+
+    match crypto::verify_ed25519(&public_key, proof.message().as_bytes(), &signature) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(driver::DriverError::InvalidInput(
+            "Proof isn't signed by the issuer".to_string(),
+        )),
+        Err(crypto::CryptoError::InvalidKey(e)) => Err(driver::DriverError::SystemError(format!(
+            "Invalid issuer_public_key: {}",
+            e
+        ))),
+        Err(crypto::CryptoError::InvalidSignature(e)) => Err(driver::DriverError::InvalidInput(
+            format!("Invalid proof signature: {}", e),
+        )),
+    }
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 != 0 {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Full years between the date of birth in `proof` and `today`, as `(year, month, day)`.
+fn age_on(proof: &AgeProof, (year, month, day): (u16, u8, u8)) -> Option<u16> {
+    let had_birthday = (month, day) >= (proof.month, proof.day);
+    year.checked_sub(proof.year)?
+        .checked_sub(if had_birthday { 0 } else { 1 })
+}
+
+/// Gregorian `(year, month, day)` of a number of days since the unix epoch.
+fn civil_date(days: u64) -> (u16, u8, u8) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as u16, month, day)
 }
 
 bindings::export!(Component with_types_in bindings);
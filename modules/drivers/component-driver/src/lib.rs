@@ -1,7 +1,12 @@
 #[allow(warnings)]
 mod bindings;
 
-use bindings::{component::units::storage, exports::component::units::driver};
+use std::collections::{BTreeMap, BTreeSet};
+
+use bindings::{
+    component::units::storage,
+    exports::component::units::{assets, driver, two_phase},
+};
 
 struct Component;
 
@@ -20,22 +25,11 @@ impl driver::Guest for Component {
     }
 
     fn transfer(fro: String, to: String, value: String) -> Result<(), driver::DriverError> {
-        let mut from_acc = serde_json::from_str::<AccountInfo>(&fro)
-            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
-        let mut to_acc = serde_json::from_str::<AccountInfo>(&to)
-            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
         let diff = serde_json::from_str::<Data>(&value)
             .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?
             .amount;
 
-        from_acc.amount -= diff;
-        to_acc.amount += diff;
-
-        storage::set(&from_acc.name, &serde_json::to_string(&from_acc).unwrap())
-            .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
-        storage::set(&to_acc.name, &serde_json::to_string(&to_acc).unwrap())
-            .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
-        Ok(())
+        move_amount(&fro, &to, diff, None)
     }
 
     fn view(input: String) -> Result<String, driver::DriverError> {
@@ -46,10 +40,255 @@ impl driver::Guest for Component {
     }
 }
 
+impl assets::Guest for Component {
+    fn transfer_v2(
+        fro: String,
+        to: String,
+        value: driver::AssetValue,
+    ) -> Result<(), driver::DriverError> {
+        let diff = value
+            .amount
+            .parse::<u64>()
+            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+
+        move_amount(&fro, &to, diff, Some(&value))
+    }
+}
+
+/// Prepared legs hold their amount on the debited account, off its available balance, so a
+/// prepared transaction always has the funds to commit and other transfers can't spend them.
+/// Commit moves the held amounts to the credited accounts and abort gives them back.
+impl two_phase::Guest for Component {
+    fn prepare(
+        txn: String,
+        fro: String,
+        to: String,
+        value: driver::AssetValue,
+    ) -> Result<(), driver::DriverError> {
+        let diff = value
+            .amount
+            .parse::<u64>()
+            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+        load_account(&to)?.check(&value)?;
+
+        // the leg is staged before its hold is taken, so an abort finds every hold it may have
+        let mut staged = load_staged(&txn)?;
+        let hold = format!("{}:{}", txn, staged.len());
+        staged.push(Staged {
+            hold: hold.clone(),
+            fro: fro.clone(),
+            to,
+            amount: diff,
+        });
+        store_staged(&txn, &staged)?;
+
+        update_account(&fro, |from_acc| {
+            from_acc.check(&value)?;
+            from_acc.amount = from_acc.amount.checked_sub(diff).ok_or_else(|| {
+                driver::DriverError::InvalidInput("Insufficient balance".to_string())
+            })?;
+            from_acc.holds.insert(hold.clone(), diff);
+            Ok(())
+        })
+    }
+
+    fn commit(txn: String) -> Result<(), driver::DriverError> {
+        // every step checks whether it already happened, so a commit retried after a failure
+        // doesn't move a leg twice
+        for leg in load_staged(&txn)? {
+            if load_account(&leg.fro)?.holds.contains_key(&leg.hold) {
+                update_account(&leg.to, |to_acc| {
+                    if to_acc.credited.insert(leg.hold.clone()) {
+                        to_acc.amount = to_acc.amount.checked_add(leg.amount).ok_or_else(|| {
+                            driver::DriverError::SystemError("Balance overflow".to_string())
+                        })?;
+                    }
+                    Ok(())
+                })?;
+                update_account(&leg.fro, |from_acc| {
+                    from_acc.holds.remove(&leg.hold);
+                    Ok(())
+                })?;
+            }
+            update_account(&leg.to, |to_acc| {
+                to_acc.credited.remove(&leg.hold);
+                Ok(())
+            })?;
+        }
+        store_staged(&txn, &[])
+    }
+
+    fn abort(txn: String) -> Result<(), driver::DriverError> {
+        for leg in load_staged(&txn)? {
+            update_account(&leg.fro, |from_acc| {
+                if let Some(held) = from_acc.holds.remove(&leg.hold) {
+                    from_acc.amount = from_acc.amount.checked_add(held).ok_or_else(|| {
+                        driver::DriverError::SystemError("Balance overflow on release".to_string())
+                    })?;
+                }
+                Ok(())
+            })?;
+        }
+        store_staged(&txn, &[])
+    }
+}
+
+fn staged_key(txn: &str) -> String {
+    format!("txn:{}", txn)
+}
+
+fn load_staged(txn: &str) -> Result<Vec<Staged>, driver::DriverError> {
+    match storage::get(&staged_key(txn)) {
+        Ok(stored) => serde_json::from_str(&stored)
+            .map_err(|e| driver::DriverError::SystemError(e.to_string())),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+fn store_staged(txn: &str, staged: &[Staged]) -> Result<(), driver::DriverError> {
+    storage::set(&staged_key(txn), &serde_json::to_string(staged).unwrap())
+        .map_err(|e| driver::DriverError::SystemError(e.to_string()))
+}
+
+fn move_amount(
+    fro: &str,
+    to: &str,
+    diff: u64,
+    value: Option<&driver::AssetValue>,
+) -> Result<(), driver::DriverError> {
+    if let Some(value) = value {
+        load_account(to)?.check(value)?;
+    }
+
+    update_account(fro, |from_acc| {
+        if let Some(value) = value {
+            from_acc.check(value)?;
+        }
+        from_acc.amount = from_acc
+            .amount
+            .checked_sub(diff)
+            .ok_or_else(|| driver::DriverError::InvalidInput("Insufficient balance".to_string()))?;
+        Ok(())
+    })?;
+
+    let credited = update_account(to, |to_acc| {
+        to_acc.amount = to_acc
+            .amount
+            .checked_add(diff)
+            .ok_or_else(|| driver::DriverError::InvalidInput("Balance overflow".to_string()))?;
+        Ok(())
+    });
+    if let Err(e) = credited {
+        // the transfer didn't happen, the debit is given back
+        update_account(fro, |from_acc| {
+            from_acc.amount = from_acc.amount.checked_add(diff).ok_or_else(|| {
+                driver::DriverError::SystemError("Balance overflow on refund".to_string())
+            })?;
+            Ok(())
+        })?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Attempts at updating an account before giving up on writes racing with ours.
+const MAX_ATTEMPTS: u32 = 16;
+
+/// Applies `update` to the stored copy of the account, or to `input` when there is none, and
+/// stores it only if no other write came in between, trying again otherwise.
+fn update_account(
+    input: &str,
+    update: impl Fn(&mut AccountInfo) -> Result<(), driver::DriverError>,
+) -> Result<(), driver::DriverError> {
+    let name = serde_json::from_str::<AccountInfo>(input)
+        .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?
+        .name;
+
+    for _ in 0..MAX_ATTEMPTS {
+        let stored = match storage::get(&name) {
+            Ok(stored) => Some(stored),
+            Err(storage::StorageError::NotFound(_)) => None,
+            Err(e) => return Err(driver::DriverError::SystemError(e.to_string())),
+        };
+        let mut account = serde_json::from_str::<AccountInfo>(stored.as_deref().unwrap_or(input))
+            .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
+        update(&mut account)?;
+
+        let swapped = storage::compare_and_swap(
+            &name,
+            stored.as_deref(),
+            &serde_json::to_string(&account).unwrap(),
+        )
+        .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
+        if swapped {
+            return Ok(());
+        }
+    }
+
+    Err(driver::DriverError::SystemError(format!(
+        "Account {} kept changing, giving up",
+        name
+    )))
+}
+
+/// The descriptor carries the account as it was at `intend`, earlier transfers in the same
+/// execution (or their compensation) may have moved the balance since, so the stored copy wins.
+fn load_account(input: &str) -> Result<AccountInfo, driver::DriverError> {
+    let account = serde_json::from_str::<AccountInfo>(input)
+        .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+    match storage::get(&account.name) {
+        Ok(stored) => serde_json::from_str::<AccountInfo>(&stored)
+            .map_err(|e| driver::DriverError::SystemError(e.to_string())),
+        Err(_) => Ok(account),
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct AccountInfo {
     name: String,
     amount: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asset: Option<String>,
+    #[serde(default)]
+    scale: u8,
+    /// Amounts held by prepared legs, by hold, taken off `amount` until they commit or abort.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    holds: BTreeMap<String, u64>,
+    /// Holds credited to the account by a commit that hasn't finished yet.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    credited: BTreeSet<String>,
+}
+
+impl AccountInfo {
+    /// Accounts without an asset accept any asset, the scale always has to match.
+    fn check(&self, value: &driver::AssetValue) -> Result<(), driver::DriverError> {
+        if self
+            .asset
+            .as_ref()
+            .is_some_and(|asset| *asset != value.asset)
+        {
+            return Err(driver::DriverError::InvalidInput(format!(
+                "Account {} does not hold {}",
+                self.name, value.asset
+            )));
+        }
+        if self.scale != value.scale {
+            return Err(driver::DriverError::InvalidInput(format!(
+                "Account {} uses scale {}, got {}",
+                self.name, self.scale, value.scale
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct Staged {
+    hold: String,
+    fro: String,
+    to: String,
+    amount: u64,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -1,7 +1,7 @@
 #[allow(warnings)]
 mod bindings;
 
-use bindings::{component::units::storage, exports::component::units::driver};
+use bindings::{component::units::storage, exports::component::units::{assets, driver}};
 
 struct Component;
 
@@ -20,25 +20,11 @@ impl driver::Guest for Component {
     }
 
     fn transfer(fro: String, to: String, value: String) -> Result<(), driver::DriverError> {
-        let mut from_acc = serde_json::from_str::<AccountInfo>(&fro)
-            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
-        let mut to_acc = serde_json::from_str::<AccountInfo>(&to)
-            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
         let diff = serde_json::from_str::<Data>(&value)
             .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?
             .amount;
 
-        from_acc.amount -= diff;
-        to_acc.amount += diff;
-
-        let solana_key1 = format!("{}:{}", "sol", from_acc.name);
-        let solana_key2 = format!("{}:{}", "sol", to_acc.name);
-
-        storage::set(&solana_key1, &serde_json::to_string(&from_acc).unwrap())
-            .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
-        storage::set(&solana_key2, &serde_json::to_string(&to_acc).unwrap())
-            .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
-        Ok(())
+        settle(&fro, &to, diff, None)
     }
 
     fn view(input: String) -> Result<String, driver::DriverError> {
@@ -50,6 +36,75 @@ impl driver::Guest for Component {
     }
 }
 
+impl assets::Guest for Component {
+    fn transfer_v2(
+        fro: String,
+        to: String,
+        value: driver::AssetValue,
+    ) -> Result<(), driver::DriverError> {
+        let diff = value
+            .amount
+            .parse::<u64>()
+            .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+
+        settle(&fro, &to, diff, Some(&value))
+    }
+}
+
+fn settle(
+    fro: &str,
+    to: &str,
+    diff: u64,
+    value: Option<&driver::AssetValue>,
+) -> Result<(), driver::DriverError> {
+    let mut from_acc = load_account(fro)?;
+    let mut to_acc = load_account(to)?;
+
+    // the token has a fixed number of decimals, so only the asset and the scale it is minted
+    // with are accepted
+    if let Some(value) = value {
+        if value.asset != TOKEN_ASSET || value.scale != TOKEN_SCALE {
+            return Err(driver::DriverError::InvalidInput(format!(
+                "Expected {} with scale {}, got {} with scale {}",
+                TOKEN_ASSET, TOKEN_SCALE, value.asset, value.scale
+            )));
+        }
+    }
+
+    from_acc.amount = from_acc
+        .amount
+        .checked_sub(diff)
+        .ok_or_else(|| driver::DriverError::InvalidInput("Insufficient balance".to_string()))?;
+    to_acc.amount = to_acc
+        .amount
+        .checked_add(diff)
+        .ok_or_else(|| driver::DriverError::InvalidInput("Balance overflow".to_string()))?;
+
+    let solana_key1 = format!("{}:{}", "sol", from_acc.name);
+    let solana_key2 = format!("{}:{}", "sol", to_acc.name);
+
+    storage::set(&solana_key1, &serde_json::to_string(&from_acc).unwrap())
+        .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
+    storage::set(&solana_key2, &serde_json::to_string(&to_acc).unwrap())
+        .map_err(|e| driver::DriverError::SystemError(e.to_string()))?;
+    Ok(())
+}
+
+/// The descriptor carries the account as it was at `intend`, the last settled copy is more recent
+/// when the account was part of an earlier transfer.
+fn load_account(input: &str) -> Result<AccountInfo, driver::DriverError> {
+    let account = serde_json::from_str::<AccountInfo>(input)
+        .map_err(|e| driver::DriverError::InvalidInput(e.to_string()))?;
+    match storage::get(&format!("{}:{}", "sol", account.name)) {
+        Ok(stored) => serde_json::from_str::<AccountInfo>(&stored)
+            .map_err(|e| driver::DriverError::SystemError(e.to_string())),
+        Err(_) => Ok(account),
+    }
+}
+
+const TOKEN_ASSET: &str = "FIN";
+const TOKEN_SCALE: u8 = 0;
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct AccountInfo {
     name: String,
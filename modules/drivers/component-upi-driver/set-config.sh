@@ -0,0 +1,20 @@
+#!/bin/bash
+
+# the host also has to be in the driver's http allowlist, see `[driver.http_allowlist]`
+# UNITS_ADMIN_TOKEN has to be the admin token the server was started with
+
+set_config() {
+grpcurl -plaintext -H "x-admin-token: ${UNITS_ADMIN_TOKEN}" -d @ localhost:8080 finternet.Driver/SetDriverConfig <<EOM
+{
+  "driver_name": "upi",
+  "key": "$1",
+  "value": "$2",
+  "secret": ${3:-false}
+}
+EOM
+}
+
+set_config host "http://${UPI_HOST}:8030"
+set_config merchant_id INTERNALTESTUAT
+set_config channel_id INTERNALTESTUATAPP
+set_config sandbox_id YESBACQ
@@ -1,14 +1,13 @@
 #[allow(warnings)]
 mod bindings;
 
-use bindings::{component::units::{http, storage}, exports::component::units::driver};
+use bindings::{component::units::{config, http, storage}, exports::component::units::{assets, driver}};
 use std::error::Error;
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use urlencoding::encode;
-use std::env;
 
 
 struct Component;
@@ -18,7 +17,7 @@ impl driver::Guest for Component {
         let mut callback_details: CallabckInfo = serde_json::from_str(&input).map_err(|e| {
             driver::DriverError::InvalidInput(e.to_string())
         })?;
-        let config = Config::new();
+        let config = Config::load()?;
         verify_vpa(&config, &mut callback_details).map_err(|e| {
             driver::DriverError::InvalidInput(e.to_string())
         })?;
@@ -42,7 +41,7 @@ impl driver::Guest for Component {
             driver::DriverError::InvalidInput(e.to_string())
         })?.amount;
 
-        let config = Config::new();
+        let config = Config::load()?;
         send_money_p2p(&config, &mut from_acc, &mut to_acc,&diff.to_string()).map_err(|e| {
             driver::DriverError::SystemError(e.to_string())
         })?;
@@ -53,7 +52,7 @@ impl driver::Guest for Component {
         let mut callback_details: CallabckInfo = serde_json::from_str(&input).map_err(|e| {
             driver::DriverError::InvalidInput(e.to_string())
         })?;
-        let config = Config::new();
+        let config = Config::load()?;
         check_balance(&config, &mut callback_details).map_err(|e| {
             driver::DriverError::InvalidInput(e.to_string())
         })?;
@@ -67,7 +66,7 @@ impl driver::Guest for Component {
         let mut callback_details: CallabckInfo = serde_json::from_str(&input).map_err(|e| {
             driver::DriverError::InvalidInput(e.to_string())
         })?;
-        let config=Config::new();
+        let config = Config::load()?;
     
         get_sms_token(&config,&mut callback_details).map_err(|e| {
             driver::DriverError::SystemError(e.to_string())
@@ -101,11 +100,48 @@ impl driver::Guest for Component {
     }
 }
 
+impl assets::Guest for Component {
+    fn transfer_v2(fro: String, to: String, value: driver::AssetValue) -> Result<(), driver::DriverError> {
+        if value.asset != "INR" {
+            return Err(driver::DriverError::InvalidInput(format!(
+                "UPI only supports INR, got {}",
+                value.asset
+            )));
+        }
+        let mut from_acc = serde_json::from_str::<CallabckInfo>(&fro).map_err(|e| {
+            driver::DriverError::InvalidInput(e.to_string())
+        })?;
+        let mut to_acc = serde_json::from_str::<CallabckInfo>(&to).map_err(|e| {
+            driver::DriverError::InvalidInput(e.to_string())
+        })?;
+
+        let amount = to_decimal(&value.amount, value.scale);
+
+        let config = Config::load()?;
+        send_money_p2p(&config, &mut from_acc, &mut to_acc, &amount).map_err(|e| {
+            driver::DriverError::SystemError(e.to_string())
+        })?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct Data {
     amount: u64,
 }
 
+/// Renders an amount in the smallest unit as the decimal string the UPI APIs expect,
+/// e.g. `("1250", 2)` becomes `"12.50"`.
+fn to_decimal(amount: &str, scale: u8) -> String {
+    let scale = scale as usize;
+    if scale == 0 {
+        return amount.to_string();
+    }
+    let padded = format!("{:0>width$}", amount, width = scale + 1);
+    let (units, fraction) = padded.split_at(padded.len() - scale);
+    format!("{}.{}", units, fraction)
+}
+
 #[warn(non_snake_case)]
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct CallabckInfo{
@@ -131,18 +167,26 @@ struct Config {
 }
 
 impl Config {
-    fn new() -> Self {
-        Config {
-            host: format!("http://{}:8030",env::var("UPI_HOST").unwrap()),
-            uri: "n8".to_string(),
-            merchant_id: "INTERNALTESTUAT".to_string(),
-            channel_id: "INTERNALTESTUATAPP".to_string(),
-            sandbox_id: "YESBACQ".to_string(),
-            bank_code: "500004".to_string(),
-        }
+    /// Reads the configuration the admin set with `SetDriverConfig`, `host` is the base url of
+    /// the UPI switch, e.g. `http://10.0.0.5:8030`.
+    fn load() -> Result<Self, driver::DriverError> {
+        Ok(Config {
+            host: required("host")?,
+            uri: config::get("uri").unwrap_or_else(|| "n8".to_string()),
+            merchant_id: required("merchant_id")?,
+            channel_id: required("channel_id")?,
+            sandbox_id: required("sandbox_id")?,
+            bank_code: config::get("bank_code").unwrap_or_else(|| "500004".to_string()),
+        })
     }
 }
 
+fn required(key: &str) -> Result<String, driver::DriverError> {
+    config::get(key).ok_or_else(|| {
+        driver::DriverError::SystemError(format!("Driver config is missing {}", key))
+    })
+}
+
 
 #[derive(Serialize, Deserialize, Debug)]
 #[warn(non_snake_case)]
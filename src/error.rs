@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::runtime_v2::types::component::module::component::units::driver::DriverError;
+use crate::runtime_v2::types::{DriverFailure, FailureKind};
+
+/// Domain of the `ErrorInfo` attached to errors raised by the runtime itself.
+pub const DOMAIN: &str = "units";
+
+/// Domain of the `ErrorInfo` attached to errors raised by a driver.
+pub const DRIVER_DOMAIN: &str = "units.driver";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{resource} not found: {name}")]
+    NotFound {
+        resource: &'static str,
+        name: String,
+    },
+    #[error("{resource} already exists: {name}")]
+    AlreadyExists {
+        resource: &'static str,
+        name: String,
+    },
+    #[error("Invalid {field}: {description}")]
+    InvalidArgument {
+        field: &'static str,
+        description: String,
+    },
+    #[error("{0}")]
+    Unauthenticated(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    DeadlineExceeded(String),
+    #[error("{0}")]
+    ResourceExhausted(String),
+    #[error(transparent)]
+    Driver(Box<DriverFailure>),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl Error {
+    pub fn not_found(resource: &'static str, name: impl Into<String>) -> Self {
+        Self::NotFound {
+            resource,
+            name: name.into(),
+        }
+    }
+
+    pub fn invalid_argument(field: &'static str, description: impl ToString) -> Self {
+        Self::InvalidArgument {
+            field,
+            description: description.to_string(),
+        }
+    }
+
+    pub fn code(&self) -> tonic::Code {
+        match self {
+            Self::NotFound { .. } => tonic::Code::NotFound,
+            Self::AlreadyExists { .. } => tonic::Code::AlreadyExists,
+            Self::InvalidArgument { .. } => tonic::Code::InvalidArgument,
+            Self::Unauthenticated(_) => tonic::Code::Unauthenticated,
+            Self::PermissionDenied(_) => tonic::Code::PermissionDenied,
+            Self::DeadlineExceeded(_) => tonic::Code::DeadlineExceeded,
+            Self::ResourceExhausted(_) => tonic::Code::ResourceExhausted,
+            Self::Driver(failure) => match failure.kind {
+                FailureKind::PermissionDenied => tonic::Code::PermissionDenied,
+                FailureKind::InvalidInput => tonic::Code::InvalidArgument,
+                FailureKind::SystemError | FailureKind::Trap => tonic::Code::Internal,
+                FailureKind::UnknownError => tonic::Code::Unknown,
+                FailureKind::Throttled => tonic::Code::ResourceExhausted,
+                FailureKind::DeadlineExceeded => tonic::Code::DeadlineExceeded,
+            },
+            Self::Internal(_) => tonic::Code::Internal,
+        }
+    }
+
+    /// Machine readable reason of the `ErrorInfo`, stable across releases.
+    pub fn reason(&self) -> String {
+        match self {
+            Self::NotFound { resource, .. } => format!("{}_NOT_FOUND", resource.to_uppercase()),
+            Self::AlreadyExists { resource, .. } => {
+                format!("{}_ALREADY_EXISTS", resource.to_uppercase())
+            }
+            Self::InvalidArgument { .. } => "INVALID_ARGUMENT".to_string(),
+            Self::Unauthenticated(_) => "UNAUTHENTICATED".to_string(),
+            Self::PermissionDenied(_) => "PERMISSION_DENIED".to_string(),
+            Self::DeadlineExceeded(_) => "DEADLINE_EXCEEDED".to_string(),
+            Self::ResourceExhausted(_) => "RESOURCE_EXHAUSTED".to_string(),
+            Self::Driver(failure) => failure.kind.as_str().to_uppercase().replace('-', "_"),
+            Self::Internal(_) => "INTERNAL".to_string(),
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        let mut details = ErrorDetails::new();
+
+        match self {
+            Self::NotFound { resource, name } | Self::AlreadyExists { resource, name } => {
+                details.set_resource_info(*resource, name.clone(), "", self.to_string());
+                details.set_error_info(self.reason(), DOMAIN, HashMap::new());
+            }
+            Self::InvalidArgument { field, description } => {
+                details.add_bad_request_violation(*field, description.clone());
+                details.set_error_info(self.reason(), DOMAIN, HashMap::new());
+            }
+            Self::Driver(failure) => {
+                let mut metadata = HashMap::from([
+                    ("driver_name".to_string(), failure.driver_info.name.clone()),
+                    (
+                        "driver_version".to_string(),
+                        failure.driver_info.version.clone(),
+                    ),
+                    ("operation".to_string(), failure.operation.to_string()),
+                    ("message".to_string(), failure.message.clone()),
+                ]);
+                if let Some(path) = &failure.path {
+                    metadata.insert("path".to_string(), path.clone());
+                }
+                details.set_error_info(self.reason(), DRIVER_DOMAIN, metadata);
+            }
+            _ => {
+                details.set_error_info(self.reason(), DOMAIN, HashMap::new());
+            }
+        }
+
+        details
+    }
+}
+
+/// Failures of driver calls, a driver that ran out of time included, keep the driver and the
+/// operation in their details.
+impl From<DriverFailure> for Error {
+    fn from(failure: DriverFailure) -> Self {
+        Self::Driver(Box::new(failure))
+    }
+}
+
+/// Errors returned to a program by the glue, used where a driver error ends up at the API
+/// without a recorded [`DriverFailure`].
+impl From<DriverError> for Error {
+    fn from(error: DriverError) -> Self {
+        match error {
+            DriverError::PermissionDenied(message) => Self::PermissionDenied(message),
+            DriverError::InvalidInput(message) => Self::invalid_argument("input", message),
+            DriverError::SystemError(message) | DriverError::UnknownError(message) => {
+                Self::Internal(anyhow::anyhow!(message))
+            }
+        }
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(error: Error) -> Self {
+        if let Error::Internal(e) = &error {
+            tracing::error!(error = ?e, "Internal error");
+        }
+
+        tonic::Status::with_error_details(error.code(), error.to_string(), error.details())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod dispatcher;
+pub mod error;
 pub mod health;
 // pub mod runtime;
 pub mod runtime_v2;
@@ -8,4 +9,4 @@ pub mod types;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, Error>;
-pub type Error = anyhow::Error;
+pub use error::Error;
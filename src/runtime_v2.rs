@@ -6,15 +6,24 @@ use anyhow::ensure;
 
 use self::types::ServerConfig;
 
+pub mod coordinator;
+pub mod crypto;
 pub mod driver;
 pub mod glue;
 pub mod integration;
+pub mod keystore;
+pub mod logging;
+pub mod manifest;
 pub mod platform;
 pub mod process;
+pub mod quota;
 pub mod resolver;
+pub mod sandbox;
+pub mod secrets;
 pub mod service;
 pub mod storage;
 pub mod types;
+pub mod webhook;
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct RuntimeConfig {
@@ -38,12 +47,17 @@ impl Runtime {
 
         let (tx, _rx) = mpsc::channel();
 
-        Ok(Self {
+        let runtime = Self {
             process_layer: process::ProcessRuntime::init(config.process).await?,
             driver_layer: driver::DriverRuntime::init(config.driver).await?,
-            platform_layer: platform::Platform::init(config.platform)?,
+            platform_layer: platform::Platform::init(config.platform).await?,
             event_sender: Arc::new(tx),
-        })
+        };
+
+        coordinator::recover(&runtime).await?;
+        tokio::spawn(coordinator::retry(runtime.clone()));
+
+        Ok(runtime)
     }
 
     pub async fn exec(
@@ -51,16 +65,16 @@ impl Runtime {
         ctx: types::UserCtx,
         module: wasmtime::component::Component,
         input: String,
-    ) -> anyhow::Result<String> {
-        let mut state = wasmtime::Store::new(
-            &self.process_layer.engine,
-            types::ProcessState::new(
-                ctx,
-                self.driver_layer,
-                self.platform_layer,
-                self.event_sender,
-            ),
+        policy: types::ProgramPolicy,
+    ) -> crate::Result<String> {
+        let mut process_state = types::ProcessState::new(
+            ctx,
+            self.driver_layer,
+            self.platform_layer,
+            self.event_sender,
         );
+        process_state.policy = policy;
+        let mut state = wasmtime::Store::new(&self.process_layer.engine, process_state);
 
         let mut linker = wasmtime::component::Linker::new(&self.process_layer.engine);
 
@@ -69,25 +83,61 @@ impl Runtime {
             |state: &mut types::ProcessState| state,
         )?;
 
-        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-            types::component::module::component::units::driver::DriverError::SystemError(
-                err.to_string(),
-            )
-        })?;
+        wasmtime_wasi::add_to_linker_async(&mut linker)?;
         let instance =
             types::component::module::ModuleWorld::instantiate_async(&mut state, &module, &linker)
-                .await?;
-
-        tracing::info!(runtime = "process", input = %input, "executing module");
+                .await
+                .map_err(|e| crate::Error::invalid_argument("program", format!("{:#}", e)))?;
+
+        let ctx = &state.data().ctx;
+        tracing::info!(
+            runtime = "process",
+            user = ctx.user_id.as_str(),
+            execution = ctx.execution_id.as_str(),
+            input = %input,
+            "executing module"
+        );
 
-        let result = instance.call_main(state, &input).await?;
+        let result = instance.call_main(&mut state, &input).await;
 
         match result {
-            Ok(output) => Ok(output),
-            Err(e) => {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(e)) => {
                 tracing::error!(?e, "Error while executing module");
-                anyhow::bail!("Error while executing module")
+                // the driver's error says more than the program's, when it's what the program
+                // passed on
+                match state.data_mut().propagated_failure(user_error_message(&e)) {
+                    Some(failure) => Err(failure.into()),
+                    None => Err(user_error(e)),
+                }
             }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn user_error_message(error: &types::component::module::UserError) -> &str {
+    use types::component::module::UserError;
+
+    match error {
+        UserError::PermissionDenied(message)
+        | UserError::InvalidInput(message)
+        | UserError::SystemError(message)
+        | UserError::Failure(message)
+        | UserError::UnknownError(message) => message,
+    }
+}
+
+fn user_error(error: types::component::module::UserError) -> crate::Error {
+    use types::component::module::UserError;
+
+    match error {
+        UserError::PermissionDenied(message) => crate::Error::PermissionDenied(message),
+        UserError::InvalidInput(message) => crate::Error::invalid_argument("input", message),
+        UserError::SystemError(message)
+        | UserError::Failure(message)
+        | UserError::UnknownError(message) => {
+            anyhow::anyhow!("Error while executing module: {}", message).into()
         }
     }
 }
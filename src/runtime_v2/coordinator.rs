@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+
+use super::driver::DriverInfo;
+use super::types::component::module::component::units::driver::{AssetValue, DriverError};
+use super::types::{self, component, DriverCall, FailureKind, ProcessState, UserCtx};
+use super::Runtime;
+
+/// Export a driver has to provide to take part in a transaction across drivers.
+pub const TWO_PHASE_EXPORT: &str = "component:units/two-phase";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionState {
+    Preparing,
+    Committing,
+    Aborting,
+    Committed,
+    Aborted,
+}
+
+impl TransactionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Preparing => "preparing",
+            Self::Committing => "committing",
+            Self::Aborting => "aborting",
+            Self::Committed => "committed",
+            Self::Aborted => "aborted",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "preparing" => Ok(Self::Preparing),
+            "committing" => Ok(Self::Committing),
+            "aborting" => Ok(Self::Aborting),
+            "committed" => Ok(Self::Committed),
+            "aborted" => Ok(Self::Aborted),
+            other => anyhow::bail!("Invalid transaction state: {}", other),
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Committed | Self::Aborted)
+    }
+}
+
+/// Entry of the coordinator log, written before every state change so that a transaction that
+/// was in flight during a crash can be driven to completion on the next start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub txn_id: String,
+    pub user_id: String,
+    pub participants: Vec<DriverInfo>,
+    pub state: TransactionState,
+}
+
+/// A single transfer of a transaction, with the account info already resolved from the
+/// descriptors.
+pub struct Leg {
+    pub driver_info: DriverInfo,
+    /// Path the source descriptor was opened on.
+    pub path: String,
+    pub fro: String,
+    pub to: String,
+    pub value: AssetValue,
+}
+
+/// Checks whether every participant exports the two-phase interface.
+pub async fn supported(
+    state: &ProcessState,
+    participants: &[DriverInfo],
+) -> Result<bool, DriverError> {
+    for driver_info in participants {
+        let driver = state
+            .get_driver(driver_info, state.driver_runtime.engine.clone())
+            .await?;
+        if !types::exports(&driver, &state.driver_runtime.engine, TWO_PHASE_EXPORT) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Runs the legs as a single transaction: every leg is prepared, and only once all of them are
+/// prepared is the decision to commit logged and sent to the participants.
+pub async fn run(state: &mut ProcessState, legs: Vec<Leg>) -> Result<(), DriverError> {
+    let mut participants: Vec<DriverInfo> = Vec::new();
+    for leg in legs.iter() {
+        if !participants.contains(&leg.driver_info) {
+            participants.push(leg.driver_info.clone());
+        }
+    }
+
+    let mut record = TransactionRecord {
+        txn_id: crate::utils::id::new(),
+        user_id: state.ctx.user_id.clone(),
+        participants,
+        state: TransactionState::Preparing,
+    };
+
+    tracing::info!(
+        txn = record.txn_id.as_str(),
+        legs = legs.len(),
+        "starting transaction"
+    );
+
+    log(state, &record).await?;
+
+    for leg in legs.iter() {
+        if let Err(error) = prepare(state, &record.txn_id, leg).await {
+            tracing::error!(
+                txn = record.txn_id.as_str(),
+                ?error,
+                "prepare failed, aborting"
+            );
+
+            record.state = TransactionState::Aborting;
+            // the abort is sent regardless, a record left in `preparing` is aborted on recovery
+            let _ = log(state, &record).await;
+            finish(state, &mut record).await;
+
+            return Err(error);
+        }
+    }
+
+    record.state = TransactionState::Committing;
+    if let Err(error) = log(state, &record).await {
+        // without a durable decision the participants can't be told to commit
+        record.state = TransactionState::Aborting;
+        finish(state, &mut record).await;
+
+        return Err(error);
+    }
+
+    // the decision is logged, a participant that missed it is sent it again by `retry`
+    if !finish(state, &mut record).await {
+        tracing::warn!(
+            txn = record.txn_id.as_str(),
+            "commit not acknowledged by every participant, retrying in the background"
+        );
+    }
+
+    tracing::info!(
+        txn = record.txn_id.as_str(),
+        state = record.state.as_str(),
+        "transaction done"
+    );
+
+    Ok(())
+}
+
+/// Drives every transaction that didn't reach a final state to completion, transactions that
+/// never logged a decision are aborted. Only run on start, when no transaction is in flight.
+pub async fn recover(runtime: &Runtime) -> anyhow::Result<()> {
+    let pending = runtime.driver_layer.transactions.pending().await?;
+
+    for mut record in pending {
+        if record.state == TransactionState::Preparing {
+            record.state = TransactionState::Aborting;
+        }
+        deliver(runtime, record).await;
+    }
+
+    Ok(())
+}
+
+/// Sends logged decisions that weren't acknowledged again, every `transaction_retry_secs`.
+/// Transactions still preparing are left to the execution running them.
+pub async fn retry(runtime: Runtime) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        runtime.driver_layer.config.transaction_retry_secs.max(1),
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick is immediate, `recover` just went through the log
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let pending = match runtime.driver_layer.transactions.pending().await {
+            Ok(pending) => pending,
+            Err(error) => {
+                tracing::error!(?error, "failed to read pending transactions");
+                continue;
+            }
+        };
+        for record in pending {
+            if record.state != TransactionState::Preparing {
+                deliver(&runtime, record).await;
+            }
+        }
+    }
+}
+
+async fn deliver(runtime: &Runtime, mut record: TransactionRecord) {
+    tracing::warn!(
+        txn = record.txn_id.as_str(),
+        state = record.state.as_str(),
+        "recovering transaction"
+    );
+
+    let mut state = ProcessState::new(
+        UserCtx::new(record.user_id.clone()),
+        runtime.driver_layer.clone(),
+        runtime.platform_layer.clone(),
+        runtime.event_sender.clone(),
+    );
+
+    finish(&mut state, &mut record).await;
+}
+
+/// Sends the logged decision to every participant, the record is only marked final once all
+/// of them acknowledged it, otherwise it is retried by `retry`. Returns whether they did.
+async fn finish(state: &mut ProcessState, record: &mut TransactionRecord) -> bool {
+    let commit = record.state == TransactionState::Committing;
+    let mut acknowledged = true;
+
+    for driver_info in record.participants.iter() {
+        let result = if commit {
+            commit_participant(state, &record.txn_id, driver_info).await
+        } else {
+            abort_participant(state, &record.txn_id, driver_info).await
+        };
+
+        if let Err(error) = result {
+            tracing::error!(
+                txn = record.txn_id.as_str(),
+                driver = driver_info.name.as_str(),
+                version = driver_info.version.as_str(),
+                ?error,
+                "participant did not acknowledge the decision"
+            );
+            acknowledged = false;
+        }
+    }
+
+    if acknowledged {
+        record.state = if commit {
+            TransactionState::Committed
+        } else {
+            TransactionState::Aborted
+        };
+        if let Err(error) = log(state, record).await {
+            tracing::error!(
+                txn = record.txn_id.as_str(),
+                ?error,
+                "failed to log final state"
+            );
+        }
+    }
+
+    acknowledged
+}
+
+async fn log(state: &ProcessState, record: &TransactionRecord) -> Result<(), DriverError> {
+    state
+        .driver_runtime
+        .transactions
+        .upsert(record)
+        .await
+        .map_err(|e| {
+            DriverError::SystemError(format!("Failed while writing transaction log: {}", e))
+        })
+}
+
+async fn instantiate(
+    state: &mut ProcessState,
+    call: &DriverCall,
+) -> Result<
+    (
+        component::transactional::TransactionalDriverWorld,
+        wasmtime::Store<types::DriverState>,
+    ),
+    DriverError,
+> {
+    let (driver, linker, mut store) = state.load_driver(call).await?;
+
+    match component::transactional::TransactionalDriverWorld::instantiate_async(
+        &mut store, &driver, &linker,
+    )
+    .await
+    {
+        Ok(instance) => Ok((instance, store)),
+        Err(e) => Err(state.fail(call.failure(
+            FailureKind::Trap,
+            format!("Failed while instantiating driver: {:#}", e),
+        ))),
+    }
+}
+
+async fn prepare(state: &mut ProcessState, txn: &str, leg: &Leg) -> Result<(), DriverError> {
+    let call = DriverCall::new(leg.driver_info.clone(), Some(leg.path.clone()), "prepare");
+    // only prepare is throttled, a decision that can't be delivered is retried in the background
+    state.admit(&call)?;
+    let (instance, store) = instantiate(state, &call).await?;
+
+    let value = component::transactional::exports::component::units::driver::AssetValue {
+        asset: leg.value.asset.clone(),
+        amount: leg.value.amount.clone(),
+        scale: leg.value.scale,
+    };
+
+    let result = types::with_deadline(
+        state.driver_runtime.driver_timeout(),
+        instance
+            .component_units_two_phase()
+            .call_prepare(store, txn, &leg.fro, &leg.to, &value),
+    )
+    .await;
+    call.check(result).map_err(|failure| state.fail(*failure))
+}
+
+async fn commit_participant(
+    state: &mut ProcessState,
+    txn: &str,
+    driver_info: &DriverInfo,
+) -> Result<(), DriverError> {
+    let call = DriverCall::new(driver_info.clone(), None, "commit");
+    let (instance, store) = instantiate(state, &call).await?;
+
+    let result = types::with_deadline(
+        state.driver_runtime.driver_timeout(),
+        instance.component_units_two_phase().call_commit(store, txn),
+    )
+    .await;
+    call.check(result).map_err(|failure| state.fail(*failure))
+}
+
+async fn abort_participant(
+    state: &mut ProcessState,
+    txn: &str,
+    driver_info: &DriverInfo,
+) -> Result<(), DriverError> {
+    let call = DriverCall::new(driver_info.clone(), None, "abort");
+    let (instance, store) = instantiate(state, &call).await?;
+
+    let result = types::with_deadline(
+        state.driver_runtime.driver_timeout(),
+        instance.component_units_two_phase().call_abort(store, txn),
+    )
+    .await;
+    call.check(result).map_err(|failure| state.fail(*failure))
+}
@@ -0,0 +1,137 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Why a signature couldn't be checked at all, as opposed to not matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    InvalidKey(String),
+    InvalidSignature(String),
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+pub fn blake3(data: &[u8]) -> Vec<u8> {
+    blake3::hash(data).as_bytes().to_vec()
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Checks an ed25519 `signature` of `message`, with the 32 byte `public_key`. Weak keys and
+/// malleable signatures don't verify.
+pub fn verify_ed25519(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, VerifyError> {
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    let public_key: &[u8; 32] = public_key.try_into().map_err(|_| {
+        VerifyError::InvalidKey(format!("Expected 32 bytes, got {}", public_key.len()))
+    })?;
+    let public_key =
+        VerifyingKey::from_bytes(public_key).map_err(|e| VerifyError::InvalidKey(e.to_string()))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|e| VerifyError::InvalidSignature(e.to_string()))?;
+
+    Ok(public_key.verify_strict(message, &signature).is_ok())
+}
+
+/// Checks a secp256k1 ECDSA `signature` of the sha256 of `message`. The key is SEC1 encoded,
+/// compressed or not, and the signature is either `r || s` or DER encoded, with any `s`.
+pub fn verify_secp256k1(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, VerifyError> {
+    use k256::ecdsa::signature::Verifier;
+    use k256::ecdsa::{Signature, VerifyingKey};
+
+    let public_key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|e| VerifyError::InvalidKey(e.to_string()))?;
+    let signature = match signature.len() {
+        64 => Signature::from_slice(signature),
+        _ => Signature::from_der(signature),
+    }
+    .map_err(|e| VerifyError::InvalidSignature(e.to_string()))?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+
+    Ok(public_key.verify(message, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes() {
+        assert_eq!(
+            hex::encode(sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex::encode(blake3(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_verify_ed25519() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let signature = key.sign(b"proof").to_bytes();
+
+        assert_eq!(verify_ed25519(&public_key, b"proof", &signature), Ok(true));
+        assert_eq!(
+            verify_ed25519(&public_key, b"forged", &signature),
+            Ok(false)
+        );
+        assert!(matches!(
+            verify_ed25519(&public_key[..31], b"proof", &signature),
+            Err(VerifyError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            verify_ed25519(&public_key, b"proof", &signature[..63]),
+            Err(VerifyError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_secp256k1() {
+        use k256::ecdsa::signature::Signer;
+        use k256::ecdsa::{Signature, SigningKey};
+
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let public_key = key.verifying_key().to_sec1_bytes();
+        let signature: Signature = key.sign(b"proof");
+
+        assert_eq!(
+            verify_secp256k1(&public_key, b"proof", &signature.to_bytes()),
+            Ok(true)
+        );
+        assert_eq!(
+            verify_secp256k1(&public_key, b"proof", signature.to_der().as_bytes()),
+            Ok(true)
+        );
+        assert_eq!(
+            verify_secp256k1(&public_key, b"forged", &signature.to_bytes()),
+            Ok(false)
+        );
+        assert!(matches!(
+            verify_secp256k1(&[4; 10], b"proof", &signature.to_bytes()),
+            Err(VerifyError::InvalidKey(_))
+        ));
+    }
+}
@@ -1,7 +1,16 @@
-use super::storage::{DriverStorage, Resolver, UserStorage};
+use super::keystore::{self, KeyAlgorithm, Keyring};
+use super::logging::GuestLogs;
+use super::manifest::DriverManifest;
+use super::quota::RateLimiter;
+use super::sandbox::{HttpAllowlist, HttpLimits, Sandbox, StorageScope};
+use super::secrets::SecretKey;
+use super::storage::{
+    ConfigEntry, DriverConfigStorage, DriverLimits, DriverStorage, KeyRecord, KeyStorage, Resolver,
+    SignatureRecord, TransactionLog, UserStorage,
+};
 use super::types;
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct DriverInfo {
     pub name: String,
     pub version: String,
@@ -10,21 +19,93 @@ pub struct DriverInfo {
 #[derive(Clone)]
 pub struct DriverRuntime {
     pub engine: wasmtime::Engine,
+    pub config: types::DriverConfig,
+    pub rate_limiter: RateLimiter,
+    pub logs: GuestLogs,
     pub drivers: Box<dyn DriverStorage>,
     pub resolver: Box<dyn Resolver>,
     pub user: Box<dyn UserStorage>,
+    pub transactions: Box<dyn TransactionLog>,
+    /// Configuration admins set for drivers, not to be confused with `config`.
+    pub driver_config: Box<dyn DriverConfigStorage>,
+    pub keys: Box<dyn KeyStorage>,
+    pub secret_key: Option<SecretKey>,
+    /// Digest of the token admin calls are authorized with, admin calls are refused without one.
+    admin_token: Option<[u8; 32]>,
+}
+
+/// Read access to the configuration of a single driver, handed to its instances.
+#[derive(Clone)]
+pub struct ConfigReader {
+    driver_name: String,
+    store: Box<dyn DriverConfigStorage>,
+    secret_key: Option<SecretKey>,
+}
+
+impl ConfigReader {
+    /// Value of `key`, decrypted when it is a secret.
+    pub async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let Some(entry) = self.store.get(&self.driver_name, key).await? else {
+            return Ok(None);
+        };
+
+        let value = match entry.secret {
+            true => self
+                .secret_key
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("No secret key to decrypt {} with", key))?
+                .open(secret_aad(&self.driver_name, key).as_bytes(), &entry.value)?,
+            false => entry.value,
+        };
+
+        Ok(Some(String::from_utf8(value)?))
+    }
+}
+
+/// Secrets are bound to the driver and key they are set for.
+fn secret_aad(driver_name: &str, key: &str) -> String {
+    format!("{}/{}", driver_name, key)
 }
 
 impl DriverRuntime {
-    pub async fn init(_config: types::DriverConfig) -> anyhow::Result<Self> {
+    pub async fn init(config: types::DriverConfig) -> anyhow::Result<Self> {
         tracing::debug!("Initializing driver runtime");
+        for (name, entries) in config.http_allowlist.iter() {
+            HttpAllowlist::parse(entries)
+                .map_err(|e| anyhow::anyhow!("Invalid http allowlist for {}: {}", name, e))?;
+        }
         let engine = wasmtime::Engine::new(wasmtime::Config::new().async_support(true)).unwrap();
         let resolver = super::storage::sql::SqliteStorage::new("sqlite:units.db").await?;
+        let secret_key = SecretKey::from_env(&config.secret_key_env);
+        if secret_key.is_none() {
+            tracing::warn!(
+                var = config.secret_key_env.as_str(),
+                "No secret key set, drivers can't be given secrets"
+            );
+        }
+        let admin_token = std::env::var(&config.admin_token_env)
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(|token| token_digest(&token));
+        if admin_token.is_none() {
+            tracing::warn!(
+                var = config.admin_token_env.as_str(),
+                "No admin token set, admin calls are refused"
+            );
+        }
         Ok(Self {
             engine,
+            rate_limiter: RateLimiter::new(config.calls_per_minute),
+            logs: GuestLogs::new(&config.logging)?,
+            config,
             drivers: Box::new(resolver.clone()),
             resolver: Box::new(resolver.clone()),
-            user: Box::new(resolver),
+            user: Box::new(resolver.clone()),
+            transactions: Box::new(resolver.clone()),
+            driver_config: Box::new(resolver.clone()),
+            keys: Box::new(resolver),
+            secret_key,
+            admin_token,
         })
     }
 
@@ -33,16 +114,325 @@ impl DriverRuntime {
         name: String,
         module: wasmtime::component::Component,
         version: String,
-    ) -> anyhow::Result<()> {
+        manifest: Option<DriverManifest>,
+    ) -> crate::Result<()> {
         let driver_info = DriverInfo { name, version };
-        self.drivers.insert(driver_info, module).await?;
+        validate_identifier("driver_name", &driver_info.name)?;
+        validate_identifier("driver_version", &driver_info.version)?;
+
+        let limits = DriverLimits {
+            drivers: self.config.driver_limit,
+            versions: self.config.max_versions,
+        };
+        if self
+            .drivers
+            .insert(driver_info.clone(), module, manifest, limits)
+            .await?
+        {
+            return Ok(());
+        }
+
+        // only used to explain the refusal, the limits were enforced by the insert
+        let loaded = self.drivers.list_info().await?;
+        let versions = loaded
+            .iter()
+            .filter(|loaded| loaded.name == driver_info.name)
+            .count();
+        if versions >= self.config.max_versions as usize {
+            return Err(crate::Error::ResourceExhausted(format!(
+                "Version limit reached: {} of {} versions of {} are loaded",
+                versions, self.config.max_versions, driver_info.name
+            )));
+        }
+
+        Err(crate::Error::ResourceExhausted(format!(
+            "Driver limit reached: {} of {} drivers are loaded",
+            loaded.len(),
+            self.config.driver_limit
+        )))
+    }
+
+    /// Checks that `token` is the admin token.
+    pub fn check_admin(&self, token: Option<&str>) -> crate::Result<()> {
+        let Some(expected) = &self.admin_token else {
+            return Err(crate::Error::PermissionDenied(
+                "Admin calls are disabled, no admin token is set".to_string(),
+            ));
+        };
+
+        match token {
+            // digests are compared so the time taken says nothing about the token
+            Some(token) if token_digest(token) == *expected => Ok(()),
+            _ => Err(crate::Error::PermissionDenied(
+                "Admin token is missing or invalid".to_string(),
+            )),
+        }
+    }
+
+    /// Checks the size of a driver binary before it is compiled.
+    pub fn check_component_size(&self, size: usize) -> crate::Result<()> {
+        if size > self.config.max_component_size {
+            return Err(crate::Error::invalid_argument(
+                "driver_binary",
+                format!(
+                    "Driver binary is {} bytes, the limit is {} bytes",
+                    size, self.config.max_component_size
+                ),
+            ));
+        }
 
         Ok(())
     }
 
+    /// Sandbox a driver runs in, what its manifest requests plus what the admin granted it.
+    pub async fn sandbox(&self, driver_info: &DriverInfo) -> anyhow::Result<Sandbox> {
+        let manifest = self
+            .drivers
+            .manifest(driver_info)
+            .await?
+            .unwrap_or_default();
+        let granted = self.config.http_allowlist.get(&driver_info.name);
+
+        let storage = &self.config.storage;
+        let namespace = match storage.version_scoped.contains(&driver_info.name) {
+            true => format!("{}@{}", driver_info.name, driver_info.version),
+            false => driver_info.name.clone(),
+        };
+        let shared = storage
+            .shared
+            .iter()
+            .filter(|(_, drivers)| drivers.contains(&driver_info.name))
+            .map(|(namespace, _)| namespace.clone())
+            .collect();
+
+        Ok(Sandbox {
+            http: HttpAllowlist::parse(
+                manifest
+                    .capabilities
+                    .http_hosts
+                    .iter()
+                    .chain(granted.into_iter().flatten()),
+            )?,
+            http_limits: HttpLimits {
+                timeout: std::time::Duration::from_millis(self.config.http_timeout_ms),
+                max_response_size: self.config.max_response_size,
+            },
+            storage: StorageScope::new(namespace, shared, manifest.capabilities.storage_prefixes),
+        })
+    }
+
+    /// Sets `key` in the configuration of a driver, or removes it when there is no `value`.
+    /// Secrets are encrypted before they are stored.
+    pub async fn set_config(
+        &self,
+        driver_name: &str,
+        key: &str,
+        value: Option<String>,
+        secret: bool,
+    ) -> crate::Result<()> {
+        validate_identifier("driver_name", driver_name)?;
+        validate_identifier("key", key)?;
+
+        tracing::info!(driver = driver_name, key, secret, "Setting driver config");
+
+        let Some(value) = value else {
+            self.driver_config.remove(driver_name, key).await?;
+            return Ok(());
+        };
+
+        let value = match secret {
+            true => {
+                let secret_key = self.secret_key.as_ref().ok_or_else(|| {
+                    crate::Error::PermissionDenied(format!(
+                        "Secrets can't be stored without a key, set {}",
+                        self.config.secret_key_env
+                    ))
+                })?;
+                secret_key.seal(secret_aad(driver_name, key).as_bytes(), value.as_bytes())?
+            }
+            false => value.into_bytes(),
+        };
+        self.driver_config
+            .set(driver_name, key, ConfigEntry { value, secret })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Configuration of a driver with its secrets redacted, as `(key, value, secret)`.
+    pub async fn redacted_config(
+        &self,
+        driver_name: &str,
+    ) -> crate::Result<Vec<(String, String, bool)>> {
+        let entries = self.driver_config.list(driver_name).await?;
+
+        Ok(entries
+            .into_iter()
+            .map(|(key, entry)| match entry.secret {
+                true => (key, String::new(), true),
+                false => (
+                    key,
+                    String::from_utf8_lossy(&entry.value).into_owned(),
+                    false,
+                ),
+            })
+            .collect())
+    }
+
+    pub fn driver_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.driver_timeout.into())
+    }
+
+    pub fn config_reader(&self, driver_info: &DriverInfo) -> ConfigReader {
+        ConfigReader {
+            driver_name: driver_info.name.clone(),
+            store: self.driver_config.clone(),
+            secret_key: self.secret_key.clone(),
+        }
+    }
+
+    /// Generates the first version of the key `key_name` for a driver.
+    pub async fn provision_key(
+        &self,
+        driver_name: &str,
+        key_name: &str,
+        algorithm: &str,
+    ) -> crate::Result<KeyRecord> {
+        validate_identifier("driver_name", driver_name)?;
+        validate_identifier("key_name", key_name)?;
+        let algorithm = KeyAlgorithm::parse(algorithm)
+            .map_err(|e| crate::Error::invalid_argument("algorithm", e))?;
+
+        if self.keys.latest(driver_name, key_name).await?.is_some() {
+            return Err(crate::Error::AlreadyExists {
+                resource: "key",
+                name: format!("{}/{}", driver_name, key_name),
+            });
+        }
+
+        self.generate_key(driver_name, key_name, 1, algorithm).await
+    }
+
+    /// Generates a new version of the key `key_name` for a driver, with the same algorithm. Drivers
+    /// sign with it from then on, older versions are kept for their public keys.
+    pub async fn rotate_key(&self, driver_name: &str, key_name: &str) -> crate::Result<KeyRecord> {
+        let latest = self
+            .keys
+            .latest(driver_name, key_name)
+            .await?
+            .ok_or_else(|| {
+                crate::Error::not_found("key", format!("{}/{}", driver_name, key_name))
+            })?;
+
+        self.generate_key(driver_name, key_name, latest.version + 1, latest.algorithm)
+            .await
+    }
+
+    async fn generate_key(
+        &self,
+        driver_name: &str,
+        key_name: &str,
+        version: u32,
+        algorithm: KeyAlgorithm,
+    ) -> crate::Result<KeyRecord> {
+        let secret_key = self.secret_key.as_ref().ok_or_else(|| {
+            crate::Error::PermissionDenied(format!(
+                "Keys can't be stored without a secret key, set {}",
+                self.config.secret_key_env
+            ))
+        })?;
+
+        tracing::info!(
+            driver = driver_name,
+            key = key_name,
+            version,
+            algorithm = algorithm.as_str(),
+            "Generating driver key"
+        );
+
+        let (public_key, private_key) = algorithm.generate();
+        let record = KeyRecord {
+            driver_name: driver_name.to_string(),
+            key_name: key_name.to_string(),
+            version,
+            algorithm,
+            public_key,
+            private_key: secret_key.seal(
+                keystore::key_aad(driver_name, key_name, version).as_bytes(),
+                &private_key,
+            )?,
+            created_at: keystore::unix_now(),
+        };
+        self.keys.insert(&record).await?;
+
+        Ok(KeyRecord {
+            private_key: Vec::new(),
+            ..record
+        })
+    }
+
+    /// Every version of the keys of a driver, without their private keys.
+    pub async fn list_keys(&self, driver_name: &str) -> crate::Result<Vec<KeyRecord>> {
+        Ok(self
+            .keys
+            .list(driver_name)
+            .await?
+            .into_iter()
+            .map(|key| KeyRecord {
+                private_key: Vec::new(),
+                ..key
+            })
+            .collect())
+    }
+
+    /// Signatures the driver made, only the ones made for `user_id` when there is one.
+    pub async fn key_signatures(
+        &self,
+        driver_name: &str,
+        user_id: Option<&str>,
+        limit: u32,
+    ) -> crate::Result<Vec<SignatureRecord>> {
+        Ok(self.keys.signatures(driver_name, user_id, limit).await?)
+    }
+
+    pub fn keyring(&self, driver_info: &DriverInfo) -> Keyring {
+        Keyring::new(
+            driver_info.clone(),
+            self.keys.clone(),
+            self.secret_key.clone(),
+        )
+    }
+
     pub async fn remove_driver(&self, driver_info: DriverInfo) -> anyhow::Result<()> {
         self.drivers.remove(&driver_info).await?;
+        self.rate_limiter.forget(&driver_info);
 
         Ok(())
     }
 }
+
+fn token_digest(token: &str) -> [u8; 32] {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(token.as_bytes()).into()
+}
+
+/// Driver names and versions end up in storage namespaces, they are kept to characters that
+/// can't be mistaken for a separator.
+fn validate_identifier(field: &'static str, value: &str) -> crate::Result<()> {
+    let valid = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'));
+    if !valid {
+        return Err(crate::Error::invalid_argument(
+            field,
+            format!(
+                "{:?} must only contain letters, digits, '-', '_', '.' and '+'",
+                value
+            ),
+        ));
+    }
+
+    Ok(())
+}
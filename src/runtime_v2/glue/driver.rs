@@ -1,13 +1,66 @@
-use crate::runtime_v2::types::component::driver::component::units::storage::{Host, StorageError};
+use crate::runtime_v2::crypto::{self as host_crypto, VerifyError};
+use crate::runtime_v2::keystore::KeyAlgorithm;
+use crate::runtime_v2::platform::KvError;
+use crate::runtime_v2::sandbox::ScopeError;
+use crate::runtime_v2::types::component::driver::component::units::storage::{
+    Host, ListPage, StorageError,
+};
+use crate::runtime_v2::types::component::driver::component::units::{
+    clock, config, crypto, keystore, logging, random,
+};
 use crate::runtime_v2::types::DriverState;
 
+impl DriverState {
+    /// Key in the platform storage for a key of the driver, see [`StorageScope`].
+    ///
+    /// [`StorageScope`]: crate::runtime_v2::sandbox::StorageScope
+    fn storage_key(&self, key: &str) -> Result<String, StorageError> {
+        self.driver_ctx
+            .sandbox
+            .storage
+            .resolve(key)
+            .map_err(|e| match e {
+                ScopeError::InvalidKey(message) => StorageError::InvalidKey(message),
+                ScopeError::PermissionDenied(message) => {
+                    tracing::warn!(
+                        runtime = "driver",
+                        driver = self.driver_ctx.driver_info.name.as_str(),
+                        version = self.driver_ctx.driver_info.version.as_str(),
+                        key,
+                        "storage access denied"
+                    );
+                    StorageError::PermissionDenied(message)
+                }
+            })
+    }
+}
+
+/// Error of a platform storage call made while `doing` something.
+fn kv_error(doing: &str, e: KvError) -> StorageError {
+    match e {
+        KvError::InvalidValue(message) => StorageError::InvalidValue(message),
+        KvError::Denied(message) => StorageError::PermissionDenied(message),
+        KvError::Backend(e) => {
+            StorageError::SystemError(format!("Failed while {}: {:?}", doing, e))
+        }
+    }
+}
+
 impl Host for DriverState {
     async fn get(&mut self, key: String) -> Result<String, StorageError> {
-        tracing::info!(runtime = "driver", call = "get", key = key.as_str());
-        let output =
-            self.platform.storage.get(&key).map_err(|e| {
-                StorageError::SystemError(format!("Failed while getting key: {:?}", e))
-            })?;
+        tracing::info!(
+            runtime = "driver",
+            call = "get",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            key = key.as_str()
+        );
+        let scoped = self.storage_key(&key)?;
+        let output = self
+            .platform
+            .storage
+            .get(&scoped)
+            .await
+            .map_err(|e| kv_error("getting key", e))?;
         match output {
             Some(value) => Ok(value),
             None => Err(StorageError::NotFound(format!("Key not found: {}", key))),
@@ -15,89 +68,458 @@ impl Host for DriverState {
     }
 
     async fn set(&mut self, key: String, value: String) -> Result<(), StorageError> {
-        tracing::info!(runtime = "driver", call = "set", key = key.as_str());
+        tracing::info!(
+            runtime = "driver",
+            call = "set",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            key = key.as_str()
+        );
+        let scoped = self.storage_key(&key)?;
+        self.platform
+            .storage
+            .set(&scoped, &value)
+            .await
+            .map_err(|e| kv_error("setting key", e))
+    }
+
+    async fn delete(&mut self, key: String) -> Result<(), StorageError> {
+        tracing::info!(
+            runtime = "driver",
+            call = "delete",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            key = key.as_str()
+        );
+        let scoped = self.storage_key(&key)?;
         self.platform
             .storage
-            .set(&key, &value)
-            .map_err(|e| StorageError::SystemError(format!("Failed while setting key: {:?}", e)))
+            .delete(&scoped)
+            .await
+            .map_err(|e| kv_error("deleting key", e))
+    }
+
+    async fn list(
+        &mut self,
+        prefix: String,
+        cursor: Option<String>,
+    ) -> Result<ListPage, StorageError> {
+        tracing::info!(
+            runtime = "driver",
+            call = "list",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            prefix = prefix.as_str()
+        );
+        let scope = &self.driver_ctx.sandbox.storage;
+        let scoped = scope.resolve_prefix(&prefix).map_err(|e| match e {
+            ScopeError::InvalidKey(message) => StorageError::InvalidKey(message),
+            ScopeError::PermissionDenied(message) => StorageError::PermissionDenied(message),
+        })?;
+        let cursor = cursor.map(|cursor| self.storage_key(&cursor)).transpose()?;
+
+        let (keys, cursor) = self
+            .platform
+            .storage
+            .list(&scoped, cursor.as_deref())
+            .await
+            .map_err(|e| kv_error("listing keys", e))?;
+        Ok(ListPage {
+            keys: keys
+                .iter()
+                .map(|key| scope.local(key).to_string())
+                .collect(),
+            cursor: cursor.map(|cursor| scope.local(&cursor).to_string()),
+        })
+    }
+
+    async fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool, StorageError> {
+        tracing::info!(
+            runtime = "driver",
+            call = "compare-and-swap",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            key = key.as_str()
+        );
+        let scoped = self.storage_key(&key)?;
+        self.platform
+            .storage
+            .compare_and_swap(&scoped, expected.as_deref(), &new)
+            .await
+            .map_err(|e| kv_error("swapping key", e))
+    }
+
+    async fn increment(&mut self, key: String, delta: i64) -> Result<i64, StorageError> {
+        tracing::info!(
+            runtime = "driver",
+            call = "increment",
+            namespace = self.driver_ctx.sandbox.storage.namespace(),
+            key = key.as_str(),
+            delta
+        );
+        let scoped = self.storage_key(&key)?;
+        self.platform
+            .storage
+            .increment(&scoped, delta)
+            .await
+            .map_err(|e| kv_error("incrementing key", e))
+    }
+}
+
+impl config::Host for DriverState {
+    async fn get(&mut self, key: String) -> Option<String> {
+        // values are never logged, they can be secrets
+        tracing::info!(
+            runtime = "driver",
+            call = "config-get",
+            driver = self.driver_ctx.driver_info.name.as_str(),
+            key = key.as_str()
+        );
+        match self.driver_ctx.config.get(&key).await {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::error!(
+                    runtime = "driver",
+                    driver = self.driver_ctx.driver_info.name.as_str(),
+                    key = key.as_str(),
+                    error = %e,
+                    "Failed to read driver config"
+                );
+                None
+            }
+        }
+    }
+}
+
+impl logging::Host for DriverState {
+    async fn log(&mut self, level: logging::Level, message: String, fields: Vec<(String, String)>) {
+        let level = match level {
+            logging::Level::Trace => tracing::Level::TRACE,
+            logging::Level::Debug => tracing::Level::DEBUG,
+            logging::Level::Info => tracing::Level::INFO,
+            logging::Level::Warn => tracing::Level::WARN,
+            logging::Level::Error => tracing::Level::ERROR,
+        };
+        let driver_ctx = &self.driver_ctx;
+        driver_ctx.logs.driver(
+            &self.ctx,
+            &driver_ctx.driver_info,
+            driver_ctx.path.as_deref(),
+            level,
+            &message,
+            &fields,
+        );
+    }
+}
+
+impl clock::Host for DriverState {
+    async fn now(&mut self) -> clock::Datetime {
+        let now = self.driver_ctx.clock.now();
+        clock::Datetime {
+            seconds: now.as_secs(),
+            nanoseconds: now.subsec_nanos(),
+        }
+    }
+
+    async fn monotonic(&mut self) -> u64 {
+        self.driver_ctx.clock.monotonic()
+    }
+}
+
+impl random::Host for DriverState {
+    async fn get_bytes(&mut self, len: u64) -> Vec<u8> {
+        self.driver_ctx.random.bytes(len)
+    }
+
+    async fn get_u64(&mut self) -> u64 {
+        rand::RngCore::next_u64(&mut self.driver_ctx.random)
+    }
+}
+
+fn crypto_error(error: VerifyError) -> crypto::CryptoError {
+    match error {
+        VerifyError::InvalidKey(message) => crypto::CryptoError::InvalidKey(message),
+        VerifyError::InvalidSignature(message) => crypto::CryptoError::InvalidSignature(message),
+    }
+}
+
+impl crypto::Host for DriverState {
+    async fn sha256(&mut self, data: Vec<u8>) -> Vec<u8> {
+        host_crypto::sha256(&data)
+    }
+
+    async fn blake3(&mut self, data: Vec<u8>) -> Vec<u8> {
+        host_crypto::blake3(&data)
+    }
+
+    async fn hmac_sha256(&mut self, key: Vec<u8>, data: Vec<u8>) -> Vec<u8> {
+        host_crypto::hmac_sha256(&key, &data)
+    }
+
+    async fn verify_ed25519(
+        &mut self,
+        public_key: Vec<u8>,
+        message: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<bool, crypto::CryptoError> {
+        host_crypto::verify_ed25519(&public_key, &message, &signature).map_err(crypto_error)
+    }
+
+    async fn verify_secp256k1(
+        &mut self,
+        public_key: Vec<u8>,
+        message: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<bool, crypto::CryptoError> {
+        host_crypto::verify_secp256k1(&public_key, &message, &signature).map_err(crypto_error)
+    }
+}
+
+impl keystore::Host for DriverState {
+    async fn public_key(
+        &mut self,
+        name: String,
+    ) -> Result<keystore::KeyInfo, keystore::KeystoreError> {
+        match self.driver_ctx.keys.key(&name).await {
+            Ok(Some(key)) => Ok(keystore::KeyInfo {
+                algorithm: match key.algorithm {
+                    KeyAlgorithm::Ed25519 => keystore::Algorithm::Ed25519,
+                    KeyAlgorithm::Secp256k1 => keystore::Algorithm::Secp256k1,
+                },
+                version: key.version,
+                public_key: key.public_key,
+            }),
+            Ok(None) => Err(keystore::KeystoreError::NotFound(format!(
+                "Key not found: {}",
+                name
+            ))),
+            Err(e) => Err(keystore::KeystoreError::SystemError(format!(
+                "Failed while reading key: {:#}",
+                e
+            ))),
+        }
+    }
+
+    async fn sign(
+        &mut self,
+        name: String,
+        payload: Vec<u8>,
+    ) -> Result<keystore::Signature, keystore::KeystoreError> {
+        tracing::info!(
+            runtime = "driver",
+            call = "sign",
+            driver = self.driver_ctx.driver_info.name.as_str(),
+            key = name.as_str()
+        );
+        match self.driver_ctx.keys.sign(&self.ctx, &name, &payload).await {
+            Ok(Some((key_version, signature))) => Ok(keystore::Signature {
+                key_version,
+                signature,
+            }),
+            Ok(None) => Err(keystore::KeystoreError::NotFound(format!(
+                "Key not found: {}",
+                name
+            ))),
+            Err(e) => {
+                tracing::error!(
+                    runtime = "driver",
+                    driver = self.driver_ctx.driver_info.name.as_str(),
+                    key = name.as_str(),
+                    error = ?e,
+                    "signing failed"
+                );
+                Err(keystore::KeystoreError::SystemError(
+                    "Failed while signing".to_string(),
+                ))
+            }
+        }
     }
 }
 
 mod http_impl {
+    use std::time::Duration;
+
     use once_cell::sync::Lazy;
-    static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+    // redirects are handed back to the driver, following them would skip the allowlist
+    static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build http client")
+    });
 
     use crate::runtime_v2::types::component::driver::component::units::*;
     use crate::runtime_v2::types::DriverState;
 
-    impl http::Host for DriverState {
-        async fn send_request(&mut self, request: http::Request) -> http::Response {
+    impl DriverState {
+        /// Sends a request on behalf of the driver, within its allowlist and limits.
+        async fn send(
+            &self,
+            request: http_v2::Request,
+        ) -> Result<http_v2::Response, http_v2::HttpError> {
+            let driver_info = &self.driver_ctx.driver_info;
+            let limits = &self.driver_ctx.sandbox.http_limits;
+
+            let url = reqwest::Url::parse(&request.url).map_err(|e| {
+                http_v2::HttpError::InvalidRequest(format!("Invalid url {:?}: {}", request.url, e))
+            })?;
+
+            let allowed = self.driver_ctx.sandbox.http.allows(&url);
+            tracing::info!(
+                runtime = "driver",
+                call = "send-request",
+                driver = driver_info.name.as_str(),
+                version = driver_info.version.as_str(),
+                scheme = url.scheme(),
+                host = url.host_str(),
+                port = url.port_or_known_default(),
+                allowed,
+                "http request"
+            );
+            if !allowed {
+                return Err(http_v2::HttpError::Denied(format!(
+                    "{}://{}:{} is not in the allowlist of {}@{}",
+                    url.scheme(),
+                    url.host_str().unwrap_or_default(),
+                    url.port_or_known_default().unwrap_or_default(),
+                    driver_info.name,
+                    driver_info.version
+                )));
+            }
+
+            let timeout = request
+                .timeout_ms
+                .map(|ms| Duration::from_millis(ms.into()).min(limits.timeout))
+                .unwrap_or(limits.timeout);
+
+            let method = match request.method {
+                http_v2::Method::Get => reqwest::Method::GET,
+                http_v2::Method::Post => reqwest::Method::POST,
+                http_v2::Method::Put => reqwest::Method::PUT,
+                http_v2::Method::Delete => reqwest::Method::DELETE,
+                http_v2::Method::Patch => reqwest::Method::PATCH,
+                http_v2::Method::Head => reqwest::Method::HEAD,
+                http_v2::Method::Options => reqwest::Method::OPTIONS,
+            };
+
             // Clone the client first to avoid any potential thread contention
             let client = HTTP_CLIENT.clone();
 
-            let response = match request.method {
-                http::Method::Get => {
-                    let mut req_builder = client.get(&request.url);
-                    for (key, value) in request.headers.iter() {
-                        req_builder = req_builder.header(key, value);
-                    }
-                    req_builder.send().await
-                }
-                http::Method::Post => {
-                    let mut req_builder = client.post(&request.url);
-                    for (key, value) in request.headers.iter() {
-                        req_builder = req_builder.header(key, value);
-                    }
-                    if let Some(body) = request.body {
-                        req_builder = req_builder.body(body);
-                    }
-                    req_builder.send().await
-                }
-                http::Method::Put => {
-                    let mut req_builder = client.put(&request.url);
-                    for (key, value) in request.headers.iter() {
-                        req_builder = req_builder.header(key, value);
-                    }
-                    if let Some(body) = request.body {
-                        req_builder = req_builder.body(body);
-                    }
-                    req_builder.send().await
-                }
-                http::Method::Delete => {
-                    let mut req_builder = client.delete(&request.url);
-                    for (key, value) in request.headers.iter() {
-                        req_builder = req_builder.header(key, value);
-                    }
-                    if let Some(body) = request.body {
-                        req_builder = req_builder.body(body);
-                    }
-                    req_builder.send().await
-                }
-            };
+            let mut req_builder = client.request(method, url).timeout(timeout);
+            for (key, value) in request.headers.iter() {
+                req_builder = req_builder.header(key, value);
+            }
+            if let Some(body) = request.body {
+                req_builder = req_builder.body(body);
+            }
 
-            let response = response.expect("failed to send request");
+            let mut response = req_builder.send().await.map_err(send_error)?;
 
+            let max = limits.max_response_size as u64;
+            if response.content_length().is_some_and(|length| length > max) {
+                return Err(http_v2::HttpError::ResponseTooLarge(max));
+            }
+
+            let status = response.status().as_u16();
             let headers = response
                 .headers()
                 .iter()
                 .map(|(name, value)| {
                     (
                         name.as_str().to_string(),
-                        value
-                            .to_str()
-                            .expect("failed to convert header value")
-                            .to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
                     )
                 })
                 .collect();
 
-            let status = response.status().as_u16();
-            let body = response.text().await.expect("failed to read body");
+            // the length can be missing or wrong, the limit is checked on what is actually read
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await.map_err(send_error)? {
+                if (body.len() + chunk.len()) as u64 > max {
+                    return Err(http_v2::HttpError::ResponseTooLarge(max));
+                }
+                body.extend_from_slice(&chunk);
+            }
 
-            http::Response {
+            Ok(http_v2::Response {
                 status,
                 headers,
                 body,
+            })
+        }
+    }
+
+    fn send_error(error: reqwest::Error) -> http_v2::HttpError {
+        if error.is_timeout() {
+            http_v2::HttpError::Timeout
+        } else if error.is_connect() {
+            http_v2::HttpError::ConnectionFailed(error.to_string())
+        } else if error.is_builder() {
+            http_v2::HttpError::InvalidRequest(error.to_string())
+        } else {
+            http_v2::HttpError::Other(error.to_string())
+        }
+    }
+
+    impl http_v2::Host for DriverState {
+        async fn send_request(
+            &mut self,
+            request: http_v2::Request,
+        ) -> Result<http_v2::Response, http_v2::HttpError> {
+            self.send(request).await.inspect_err(|error| {
+                tracing::warn!(
+                    runtime = "driver",
+                    call = "send-request",
+                    driver = self.driver_ctx.driver_info.name.as_str(),
+                    ?error,
+                    "http request failed"
+                );
+            })
+        }
+    }
+
+    /// The first version can't return an error, failures are reported to the driver as an
+    /// error status with the reason in the body.
+    impl http::Host for DriverState {
+        async fn send_request(&mut self, request: http::Request) -> http::Response {
+            let request = http_v2::Request {
+                method: match request.method {
+                    http::Method::Get => http_v2::Method::Get,
+                    http::Method::Post => http_v2::Method::Post,
+                    http::Method::Put => http_v2::Method::Put,
+                    http::Method::Delete => http_v2::Method::Delete,
+                },
+                url: request.url,
+                headers: request.headers,
+                body: request.body.map(String::into_bytes),
+                timeout_ms: None,
+            };
+
+            match http_v2::Host::send_request(self, request).await {
+                Ok(response) => http::Response {
+                    status: response.status,
+                    headers: response.headers,
+                    body: String::from_utf8_lossy(&response.body).into_owned(),
+                },
+                Err(error) => {
+                    let (status, body) = match error {
+                        http_v2::HttpError::Denied(reason) => (403, reason),
+                        http_v2::HttpError::InvalidRequest(reason) => (400, reason),
+                        http_v2::HttpError::Timeout => (504, "Request timed out".to_string()),
+                        http_v2::HttpError::ConnectionFailed(reason) => (502, reason),
+                        http_v2::HttpError::ResponseTooLarge(max) => {
+                            (502, format!("Response is larger than {} bytes", max))
+                        }
+                        http_v2::HttpError::Other(reason) => (502, reason),
+                    };
+
+                    http::Response {
+                        status,
+                        headers: vec![],
+                        body,
+                    }
+                }
             }
         }
     }
@@ -1,7 +1,13 @@
+use wasmtime::component::Resource;
+
+use crate::runtime_v2::coordinator;
 use crate::runtime_v2::driver::DriverInfo;
 use crate::runtime_v2::types;
+use crate::runtime_v2::types::component::assets::AssetDriverWorld;
+use crate::runtime_v2::types::component::driver::DriverWorld;
 use crate::runtime_v2::types::component::module::component::units;
 use crate::runtime_v2::types::component::module::component::units::driver::DriverError;
+use crate::runtime_v2::types::DriverCall;
 
 impl units::driver::Host for types::ProcessState {
     async fn intend(&mut self, input: String) -> Result<String, DriverError> {
@@ -11,55 +17,8 @@ impl units::driver::Host for types::ProcessState {
             call = "intend",
             input = input.as_str()
         );
-        let path = if let Some(suffix) = input.strip_prefix("~/") {
-            format!("/accounts/{}/{}", self.ctx.user_id, suffix)
-        } else {
-            input.clone()
-        };
-
-        let path_info = self.get_path_info(path).await?;
-
-        let driver_info = DriverInfo {
-            name: path_info.driver_name.clone(),
-            version: path_info.driver_version.clone(),
-        };
-        let account_info = path_info.account_info.clone();
-
-        let driver = self
-            .get_driver(&driver_info, self.driver_runtime.engine.clone())
-            .await?;
-        let (mut linker, mut state) = self.get_lower_runtime(driver_info)?;
-        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-            types::component::module::component::units::driver::DriverError::SystemError(
-                err.to_string(),
-            )
-        })?;
-        let instance =
-            types::component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                .await
-                .map_err(|_| {
-                    DriverError::SystemError("Failed while instantiating driver".to_string())
-                })?;
-
-        let result = instance
-            .component_units_driver()
-            .call_intend(state, &account_info)
-            .await
-            .map_err(|_| DriverError::SystemError("Failed while calling intend".to_string()))?
-            .map_err(|_| DriverError::SystemError("Failed while calling intend".to_string()))?;
-
-        let key = crate::utils::id::new();
-
-        self.descriptors.insert(
-            key.clone(),
-            types::Descriptor {
-                driver_name: path_info.driver_name,
-                driver_version: path_info.driver_version,
-                account_info: serde_json::from_str(&result).map_err(|_| {
-                    DriverError::SystemError("Failed while parsing account info".to_string())
-                })?,
-            },
-        );
+        let resource = self.open_descriptor(input, false).await?;
+        let key = self.descriptor_key(&resource)?;
 
         tracing::info!(
             loc = "end",
@@ -78,46 +37,24 @@ impl units::driver::Host for types::ProcessState {
             call = "done",
             input = input.as_str()
         );
-        let descriptor = self
-            .descriptors
-            .get(&input)
-            .ok_or(DriverError::InvalidInput(
-                "Failed while finding descriptor".to_string(),
-            ))?;
-
-        let driver_info = DriverInfo {
-            name: descriptor.driver_name.clone(),
-            version: descriptor.driver_version.clone(),
-        };
+        let descriptor = self.get_descriptor(input.clone())?;
+        let call = DriverCall::on(&descriptor, "done");
 
         let account_info = serde_json::to_string(&descriptor.account_info).map_err(|_| {
             DriverError::SystemError("Failed while serializing account info".to_string())
         })?;
 
-        let driver = self
-            .get_driver(&driver_info, self.driver_runtime.engine.clone())
-            .await?;
-        let (mut linker, mut state) = self.get_lower_runtime(driver_info)?;
-        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-            types::component::module::component::units::driver::DriverError::SystemError(
-                err.to_string(),
-            )
-        })?;
-        let instance =
-            types::component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                .await
-                .map_err(|_| {
-                    DriverError::SystemError("Failed while instantiating driver".to_string())
-                })?;
-
-        instance
-            .component_units_driver()
-            .call_done(state, &account_info)
-            .await
-            .map_err(|_| DriverError::SystemError("Failed while calling done".to_string()))?
-            .map_err(|_| DriverError::SystemError("Failed while calling done".to_string()))?;
+        let (instance, state) = self.instantiate_driver(&call).await?;
+        let result = types::with_deadline(
+            self.driver_runtime.driver_timeout(),
+            instance
+                .component_units_driver()
+                .call_done(state, &account_info),
+        )
+        .await;
+        call.check(result).map_err(|failure| self.fail(*failure))?;
 
-        self.descriptors.remove(&input);
+        self.delete_descriptor(input.clone())?;
 
         tracing::info!(
             loc = "end",
@@ -154,36 +91,26 @@ impl units::driver::Host for types::ProcessState {
             DriverError::SystemError("Failed while serializing account info".to_string())
         })?;
 
-        let driver_info = DriverInfo {
-            name: d_1.driver_name.clone(),
-            version: d_2.driver_version.clone(),
-        };
-
-        assert_eq!(d_1.driver_name, d_2.driver_name);
-        assert_eq!(d_1.driver_version, d_2.driver_version);
+        if d_1.driver_name != d_2.driver_name || d_1.driver_version != d_2.driver_version {
+            return Err(DriverError::InvalidInput(
+                "Transfer across different drivers is not supported".to_string(),
+            ));
+        }
 
-        let driver = self
-            .get_driver(&driver_info, self.driver_runtime.engine.clone())
+        let call = DriverCall::on(&d_1, "transfer");
+        let parsed =
+            serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value.clone()));
+        self.check_transfer_value(&call.driver_info, &parsed)
             .await?;
-        let (mut linker, mut state) = self.get_lower_runtime(driver_info)?;
-        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-            types::component::module::component::units::driver::DriverError::SystemError(
-                err.to_string(),
-            )
-        })?;
-        let instance =
-            types::component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                .await
-                .map_err(|_| {
-                    DriverError::SystemError("Failed while instantiating driver".to_string())
-                })?;
-
-        instance
-            .component_units_driver()
-            .call_transfer(state, &acc_1, &acc_2, &value)
-            .await
-            .map_err(|_| DriverError::SystemError("Failed while calling transfer".to_string()))?
-            .map_err(|_| DriverError::SystemError("Failed while calling transfer".to_string()))?;
+        let (instance, state) = self.instantiate_driver(&call).await?;
+        let result = types::with_deadline(
+            self.driver_runtime.driver_timeout(),
+            instance
+                .component_units_driver()
+                .call_transfer(state, &acc_1, &acc_2, &value),
+        )
+        .await;
+        call.check(result).map_err(|failure| self.fail(*failure))?;
 
         tracing::info!(loc = "end", runtime = "process", call = "transfer");
 
@@ -203,34 +130,16 @@ impl units::driver::Host for types::ProcessState {
             DriverError::SystemError("Failed while serializing account info".to_string())
         })?;
 
-        let driver_info = DriverInfo {
-            name: descriptor.driver_name.clone(),
-            version: descriptor.driver_version.clone(),
-        };
-
-        let driver = self
-            .get_driver(&driver_info, self.driver_runtime.engine.clone())
-            .await?;
-
-        let (mut linker, mut state) = self.get_lower_runtime(driver_info)?;
-        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-            types::component::module::component::units::driver::DriverError::SystemError(
-                err.to_string(),
-            )
-        })?;
-        let instance =
-            types::component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                .await
-                .map_err(|_| {
-                    DriverError::SystemError("Failed while instantiating driver".to_string())
-                })?;
-
-        let result = instance
-            .component_units_driver()
-            .call_view(state, &account_info)
-            .await
-            .map_err(|_| DriverError::SystemError("Failed while calling view".to_string()))?
-            .map_err(|_| DriverError::SystemError("Failed while calling view".to_string()))?;
+        let call = DriverCall::on(&descriptor, "view");
+        let (instance, state) = self.instantiate_driver(&call).await?;
+        let result = types::with_deadline(
+            self.driver_runtime.driver_timeout(),
+            instance
+                .component_units_driver()
+                .call_view(state, &account_info),
+        )
+        .await;
+        let result = call.check(result).map_err(|failure| self.fail(*failure))?;
 
         tracing::info!(
             loc = "end",
@@ -252,3 +161,623 @@ impl units::driver::Host for types::ProcessState {
         ))
     }
 }
+
+impl units::assets::Host for types::ProcessState {
+    async fn transfer_v2(
+        &mut self,
+        fro: String,
+        to: String,
+        value: units::driver::AssetValue,
+    ) -> Result<(), DriverError> {
+        tracing::info!(
+            loc = "start",
+            runtime = "process",
+            call = "transfer-v2",
+            from = fro.as_str(),
+            to = to.as_str(),
+            asset = value.asset.as_str(),
+            amount = value.amount.as_str(),
+            scale = value.scale
+        );
+
+        validate_value(&value)?;
+        self.move_value(fro, to, value, false).await?;
+
+        tracing::info!(loc = "end", runtime = "process", call = "transfer-v2");
+
+        Ok(())
+    }
+}
+
+impl units::batch::Host for types::ProcessState {
+    async fn transfer_batch(&mut self, legs: Vec<units::batch::Leg>) -> Result<(), DriverError> {
+        tracing::info!(
+            loc = "start",
+            runtime = "process",
+            call = "transfer-batch",
+            legs = legs.len()
+        );
+
+        if legs.is_empty() {
+            return Err(DriverError::InvalidInput(
+                "Batch must contain at least one leg".to_string(),
+            ));
+        }
+
+        // everything that can be checked upfront is checked before the first leg runs, so a
+        // malformed batch never needs compensation
+        let mut resolved = Vec::with_capacity(legs.len());
+        let mut participants: Vec<DriverInfo> = Vec::new();
+        for leg in legs.iter() {
+            validate_value(&leg.value)?;
+            let d_1 = self.get_descriptor(leg.fro.clone())?;
+            let d_2 = self.get_descriptor(leg.to.clone())?;
+            if d_1.driver_name != d_2.driver_name || d_1.driver_version != d_2.driver_version {
+                return Err(DriverError::InvalidInput(
+                    "Transfer across different drivers is not supported".to_string(),
+                ));
+            }
+
+            let driver_info = DriverInfo {
+                name: d_1.driver_name.clone(),
+                version: d_1.driver_version.clone(),
+            };
+            self.check_transfer_value(&driver_info, &value_json(&leg.value))
+                .await?;
+            if !participants.contains(&driver_info) {
+                participants.push(driver_info.clone());
+            }
+
+            resolved.push(coordinator::Leg {
+                driver_info,
+                path: d_1.path.clone(),
+                fro: serde_json::to_string(&d_1.account_info).map_err(|_| {
+                    DriverError::SystemError("Failed while serializing account info".to_string())
+                })?,
+                to: serde_json::to_string(&d_2.account_info).map_err(|_| {
+                    DriverError::SystemError("Failed while serializing account info".to_string())
+                })?,
+                value: leg.value.clone(),
+            });
+        }
+
+        // a batch touching several drivers is only run when every driver supports two-phase
+        // commit, compensation can't undo what another driver already published
+        if participants.len() > 1 {
+            if !coordinator::supported(self, &participants).await? {
+                return Err(DriverError::InvalidInput(
+                    "Batch spans drivers that don't all support two-phase commit".to_string(),
+                ));
+            }
+
+            coordinator::run(self, resolved).await?;
+
+            tracing::info!(loc = "end", runtime = "process", call = "transfer-batch");
+
+            return Ok(());
+        }
+
+        for (index, leg) in legs.iter().enumerate() {
+            let result = units::assets::Host::transfer_v2(
+                self,
+                leg.fro.clone(),
+                leg.to.clone(),
+                leg.value.clone(),
+            )
+            .await;
+
+            if let Err(error) = result {
+                tracing::error!(
+                    runtime = "process",
+                    call = "transfer-batch",
+                    leg = index,
+                    ?error,
+                    "leg failed, compensating completed legs"
+                );
+                return Err(self.compensate(&legs[..index], index, error).await);
+            }
+        }
+
+        tracing::info!(loc = "end", runtime = "process", call = "transfer-batch");
+
+        Ok(())
+    }
+}
+
+impl types::ProcessState {
+    /// Opens a descriptor of the path `input` resolves to in the resource table, `handle` when
+    /// the program gets it as a resource.
+    async fn open_descriptor(
+        &mut self,
+        input: String,
+        handle: bool,
+    ) -> Result<Resource<types::Descriptor>, DriverError> {
+        let path = self.ctx.expand_path(&input);
+
+        let path_info = self.get_path_info(path.clone()).await?;
+
+        let driver_info = DriverInfo {
+            name: path_info.driver_name.clone(),
+            version: path_info.driver_version.clone(),
+        };
+        let account_info = path_info.account_info.clone();
+
+        let call = DriverCall::new(driver_info, Some(path.clone()), "intend");
+        let (instance, state) = self.instantiate_driver(&call).await?;
+        let result = types::with_deadline(
+            self.driver_runtime.driver_timeout(),
+            instance
+                .component_units_driver()
+                .call_intend(state, &account_info),
+        )
+        .await;
+        let result = call.check(result).map_err(|failure| self.fail(*failure))?;
+
+        if let Err(e) = self
+            .manifest(&call.driver_info)
+            .await?
+            .validate_account_info(&result)
+        {
+            return Err(self.fail(call.failure(
+                types::FailureKind::SystemError,
+                format!("Driver returned invalid account info: {}", e),
+            )));
+        }
+        let account_info = match serde_json::from_str(&result) {
+            Ok(account_info) => account_info,
+            Err(e) => {
+                return Err(self.fail(call.failure(
+                    types::FailureKind::SystemError,
+                    format!("Driver returned invalid account info: {}", e),
+                )))
+            }
+        };
+        let serial = self.descriptor_serial;
+        self.descriptor_serial += 1;
+        self.table()
+            .push(types::Descriptor {
+                path,
+                driver_name: path_info.driver_name,
+                driver_version: path_info.driver_version,
+                account_info,
+                serial,
+                handle,
+                closed: false,
+            })
+            .map_err(|e| DriverError::SystemError(e.to_string()))
+    }
+
+    /// Transfers `value` from the account of `fro` to the one of `to`. Compensations are made by
+    /// the host to undo legs of a batch, they aren't admitted against the rate limit nor checked
+    /// against the manifest, so a throttled or restricted driver still gets its legs reversed.
+    async fn move_value(
+        &mut self,
+        fro: String,
+        to: String,
+        value: units::driver::AssetValue,
+        compensating: bool,
+    ) -> Result<(), DriverError> {
+        let d_1 = self.get_descriptor(fro)?;
+        let d_2 = self.get_descriptor(to)?;
+
+        if d_1.driver_name != d_2.driver_name || d_1.driver_version != d_2.driver_version {
+            return Err(DriverError::InvalidInput(
+                "Transfer across different drivers is not supported".to_string(),
+            ));
+        }
+
+        let acc_1 = serde_json::to_string(&d_1.account_info).map_err(|_| {
+            DriverError::SystemError("Failed while serializing account info".to_string())
+        })?;
+        let acc_2 = serde_json::to_string(&d_2.account_info).map_err(|_| {
+            DriverError::SystemError("Failed while serializing account info".to_string())
+        })?;
+
+        let operation = match compensating {
+            true => "compensate",
+            false => "transfer-v2",
+        };
+        let call = DriverCall::on(&d_1, operation);
+        let (driver, linker, mut store) = if compensating {
+            self.load_driver_ungated(&call).await?
+        } else {
+            self.check_transfer_value(&call.driver_info, &value_json(&value))
+                .await?;
+            self.admit(&call)?;
+            self.load_driver(&call).await?
+        };
+
+        // drivers built before typed values existed only export the legacy transfer
+        let result = if types::exports(&driver, &self.driver_runtime.engine, types::ASSETS_EXPORT) {
+            let instance =
+                match AssetDriverWorld::instantiate_async(&mut store, &driver, &linker).await {
+                    Ok(instance) => instance,
+                    Err(e) => {
+                        return Err(self.fail(call.failure(
+                            types::FailureKind::Trap,
+                            format!("Failed while instantiating driver: {:#}", e),
+                        )))
+                    }
+                };
+            let value = types::component::assets::exports::component::units::driver::AssetValue {
+                asset: value.asset,
+                amount: value.amount,
+                scale: value.scale,
+            };
+            let result = types::with_deadline(
+                self.driver_runtime.driver_timeout(),
+                instance
+                    .component_units_assets()
+                    .call_transfer_v2(store, &acc_1, &acc_2, &value),
+            )
+            .await;
+            call.check(result)
+        } else {
+            let value = legacy_value(&value)?;
+            let instance = match DriverWorld::instantiate_async(&mut store, &driver, &linker).await
+            {
+                Ok(instance) => instance,
+                Err(e) => {
+                    return Err(self.fail(call.failure(
+                        types::FailureKind::Trap,
+                        format!("Failed while instantiating driver: {:#}", e),
+                    )))
+                }
+            };
+            let result = types::with_deadline(
+                self.driver_runtime.driver_timeout(),
+                instance
+                    .component_units_driver()
+                    .call_transfer(store, &acc_1, &acc_2, &value),
+            )
+            .await;
+            call.check(result)
+        };
+        result.map_err(|failure| self.fail(*failure))
+    }
+
+    /// Checks the value of a transfer against the schema the driver declared for it.
+    async fn check_transfer_value(
+        &self,
+        driver_info: &DriverInfo,
+        value: &serde_json::Value,
+    ) -> Result<(), DriverError> {
+        self.manifest(driver_info)
+            .await?
+            .validate_transfer_value(value)
+            .map_err(|e| DriverError::InvalidInput(format!("Invalid transfer value: {}", e)))
+    }
+
+    /// Reverts the completed legs, newest first, by transferring the same value back. Returns
+    /// the error that should be reported to the program for the leg at `failed`, or one naming
+    /// the legs that couldn't be reversed.
+    async fn compensate(
+        &mut self,
+        completed: &[units::batch::Leg],
+        failed: usize,
+        error: DriverError,
+    ) -> DriverError {
+        let mut unreversed = Vec::new();
+        for (index, leg) in completed.iter().enumerate().rev() {
+            let result = self
+                .move_value(leg.to.clone(), leg.fro.clone(), leg.value.clone(), true)
+                .await;
+
+            if let Err(compensation_error) = result {
+                tracing::error!(
+                    runtime = "process",
+                    call = "transfer-batch",
+                    leg = index,
+                    error = ?compensation_error,
+                    "failed to compensate leg, batch is partially applied"
+                );
+                unreversed.push(index);
+            }
+        }
+
+        if unreversed.is_empty() {
+            return error;
+        }
+        unreversed.reverse();
+        DriverError::SystemError(format!(
+            "Batch partially applied: leg {} failed ({:?}) and legs {:?} could not be reversed",
+            failed, error, unreversed
+        ))
+    }
+}
+
+/// Asset value as the transfer value schemas of drivers see it.
+fn value_json(value: &units::driver::AssetValue) -> serde_json::Value {
+    serde_json::json!({
+        "asset": value.asset,
+        "amount": value.amount,
+        "scale": value.scale,
+    })
+}
+
+/// Value handed to the legacy `transfer` of drivers that don't export `assets`.
+fn legacy_value(value: &units::driver::AssetValue) -> Result<String, DriverError> {
+    let amount = value.amount.parse::<u64>().map_err(|_| {
+        DriverError::InvalidInput(format!(
+            "Amount {} is too large for a driver without typed values",
+            value.amount
+        ))
+    })?;
+
+    Ok(serde_json::json!({
+        "amount": amount,
+        "asset": value.asset,
+        "scale": value.scale,
+    })
+    .to_string())
+}
+
+/// Largest number of decimal places a `u128` amount can carry.
+const MAX_SCALE: u8 = 38;
+
+/// Checks that the value handed by the program is well formed before any driver sees it, so
+/// drivers can rely on `asset` being a code and `amount` being an integer that fits in a `u128`.
+fn validate_value(value: &units::driver::AssetValue) -> Result<(), DriverError> {
+    let valid_asset = !value.asset.is_empty()
+        && value.asset.len() <= 32
+        && value
+            .asset
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'));
+    if !valid_asset {
+        return Err(DriverError::InvalidInput(format!(
+            "Invalid asset code: {:?}",
+            value.asset
+        )));
+    }
+
+    if value.amount.is_empty() || !value.amount.chars().all(|c| c.is_ascii_digit()) {
+        return Err(DriverError::InvalidInput(format!(
+            "Amount must be an unsigned integer: {:?}",
+            value.amount
+        )));
+    }
+    value.amount.parse::<u128>().map_err(|_| {
+        DriverError::InvalidInput(format!("Amount is out of range: {}", value.amount))
+    })?;
+
+    if value.scale > MAX_SCALE {
+        return Err(DriverError::InvalidInput(format!(
+            "Scale must be at most {}: {}",
+            MAX_SCALE, value.scale
+        )));
+    }
+
+    Ok(())
+}
+
+impl units::descriptors::Host for types::ProcessState {}
+
+/// The resource is a typed handle over the same table entries the string based calls use, its
+/// methods reach them through the key of the entry.
+impl units::descriptors::HostDescriptor for types::ProcessState {
+    async fn open(&mut self, path: String) -> Result<Resource<types::Descriptor>, DriverError> {
+        tracing::info!(runtime = "process", call = "open", path = path.as_str());
+        self.open_descriptor(path, true).await
+    }
+
+    async fn view(&mut self, self_: Resource<types::Descriptor>) -> Result<String, DriverError> {
+        let key = self.descriptor_key(&self_)?;
+        units::driver::Host::view(self, key).await
+    }
+
+    async fn transfer_to(
+        &mut self,
+        self_: Resource<types::Descriptor>,
+        to: Resource<types::Descriptor>,
+        value: units::driver::AssetValue,
+    ) -> Result<(), DriverError> {
+        let (fro, to) = (self.descriptor_key(&self_)?, self.descriptor_key(&to)?);
+        units::assets::Host::transfer_v2(self, fro, to, value).await
+    }
+
+    async fn done(&mut self, self_: Resource<types::Descriptor>) -> Result<(), DriverError> {
+        let key = self.descriptor_key(&self_)?;
+        units::driver::Host::done(self, key).await
+    }
+
+    async fn drop(&mut self, rep: Resource<types::Descriptor>) -> wasmtime::Result<()> {
+        // a descriptor that was explicitly marked done is only closed, its entry goes here
+        if let Ok(key) = self.descriptor_key(&rep) {
+            if let Err(error) = units::driver::Host::done(self, key).await {
+                tracing::error!(
+                    runtime = "process",
+                    call = "drop",
+                    ?error,
+                    "failed to mark done"
+                );
+            }
+        }
+        self.table().delete(rep)?;
+
+        Ok(())
+    }
+}
+
+impl units::resolver::Host for types::ProcessState {
+    async fn list_paths(
+        &mut self,
+        prefix: String,
+    ) -> Result<Vec<units::resolver::PathEntry>, DriverError> {
+        tracing::info!(
+            loc = "start",
+            runtime = "process",
+            call = "list-paths",
+            prefix = prefix.as_str()
+        );
+
+        // Only the paths under the user's home are visible, a prefix above the home is narrowed
+        // down to it, and anything else belongs to someone else.
+        let home = self.ctx.home();
+        let prefix = self.ctx.expand_path(&prefix);
+        let scope = if prefix.starts_with(&home) {
+            prefix
+        } else if home.starts_with(&prefix) {
+            home
+        } else {
+            return Err(DriverError::PermissionDenied(format!(
+                "Path prefix is outside of the user namespace: {}",
+                prefix
+            )));
+        };
+
+        let entries = self
+            .driver_runtime
+            .resolver
+            .list_prefix(&scope)
+            .await
+            .into_iter()
+            .map(|(path, path_info)| units::resolver::PathEntry {
+                path,
+                driver_name: path_info.driver_name,
+                driver_version: path_info.driver_version,
+            })
+            .collect::<Vec<_>>();
+
+        tracing::info!(
+            loc = "end",
+            runtime = "process",
+            call = "list-paths",
+            count = entries.len()
+        );
+
+        Ok(entries)
+    }
+
+    async fn bind(
+        &mut self,
+        path: String,
+        driver: units::resolver::DriverRef,
+        account_info: String,
+    ) -> Result<(), DriverError> {
+        tracing::info!(
+            loc = "start",
+            runtime = "process",
+            call = "bind",
+            path = path.as_str(),
+            driver = driver.name.as_str(),
+            version = driver.version.as_str()
+        );
+
+        if !self.policy.allow_bind {
+            return Err(DriverError::PermissionDenied(
+                "Program is not allowed to bind paths".to_string(),
+            ));
+        }
+
+        let home = self.ctx.home();
+        let path = self.ctx.expand_path(&path);
+        if !path.starts_with(&home) || path.len() == home.len() {
+            return Err(DriverError::PermissionDenied(format!(
+                "Path is outside of the user namespace: {}",
+                path
+            )));
+        }
+
+        self.perform_bind(
+            path,
+            DriverInfo {
+                name: driver.name,
+                version: driver.version,
+            },
+            account_info,
+        )
+        .await?;
+
+        tracing::info!(loc = "end", runtime = "process", call = "bind");
+
+        Ok(())
+    }
+}
+
+impl units::logging::Host for types::ProcessState {
+    async fn log(
+        &mut self,
+        level: units::logging::Level,
+        message: String,
+        fields: Vec<(String, String)>,
+    ) {
+        use units::logging::Level;
+
+        let level = match level {
+            Level::Trace => tracing::Level::TRACE,
+            Level::Debug => tracing::Level::DEBUG,
+            Level::Info => tracing::Level::INFO,
+            Level::Warn => tracing::Level::WARN,
+            Level::Error => tracing::Level::ERROR,
+        };
+        self.driver_runtime
+            .logs
+            .program(&self.ctx, level, &message, &fields);
+    }
+}
+
+impl units::clock::Host for types::ProcessState {
+    async fn now(&mut self) -> units::clock::Datetime {
+        let now = self.clock.now();
+        units::clock::Datetime {
+            seconds: now.as_secs(),
+            nanoseconds: now.subsec_nanos(),
+        }
+    }
+
+    async fn monotonic(&mut self) -> u64 {
+        self.clock.monotonic()
+    }
+}
+
+impl units::random::Host for types::ProcessState {
+    async fn get_bytes(&mut self, len: u64) -> Vec<u8> {
+        self.random.bytes(len)
+    }
+
+    async fn get_u64(&mut self) -> u64 {
+        rand::RngCore::next_u64(&mut self.random)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(asset: &str, amount: &str, scale: u8) -> units::driver::AssetValue {
+        units::driver::AssetValue {
+            asset: asset.to_string(),
+            amount: amount.to_string(),
+            scale,
+        }
+    }
+
+    #[test]
+    fn test_validate_value() {
+        assert!(validate_value(&value("USD", "1250", 2)).is_ok());
+        assert!(validate_value(&value("USDC", "0", 6)).is_ok());
+        assert!(validate_value(&value("USD", &u128::MAX.to_string(), 0)).is_ok());
+
+        assert!(validate_value(&value("", "1", 0)).is_err());
+        assert!(validate_value(&value("usd", "1", 0)).is_err());
+        assert!(validate_value(&value("USD", "", 0)).is_err());
+        assert!(validate_value(&value("USD", "-1", 0)).is_err());
+        assert!(validate_value(&value("USD", "12.50", 2)).is_err());
+        assert!(
+            validate_value(&value("USD", "340282366920938463463374607431768211456", 0)).is_err()
+        );
+        assert!(validate_value(&value("USD", "1", 39)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_value() {
+        let legacy: serde_json::Value =
+            serde_json::from_str(&legacy_value(&value("USD", "1250", 2)).unwrap()).unwrap();
+        assert_eq!(legacy["amount"], 1250);
+        assert_eq!(legacy["asset"], "USD");
+        assert_eq!(legacy["scale"], 2);
+
+        assert!(legacy_value(&value("USD", &u128::MAX.to_string(), 0)).is_err());
+    }
+}
@@ -5,16 +5,26 @@ use serde::{Deserialize, Serialize};
 pub struct TransferRequest {
     pub key: String,
     pub value: String,
+    /// Same for every attempt at a settlement, so a repeated transfer can be recognised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
 }
-#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TransferResponse {
-    signature: String,
+    pub signature: String,
 }
 
-pub fn transfer_token(key: String, value: String) -> Result<TransferResponse> {
+pub fn transfer_token(
+    key: String,
+    value: String,
+    idempotency_key: Option<String>,
+) -> Result<TransferResponse> {
     let function = || async move {
-        let request = TransferRequest { key, value };
+        let request = TransferRequest {
+            key,
+            value,
+            idempotency_key,
+        };
         let response =
             surf::post("https://finternet-solana-apis-production.up.railway.app/token/transfer")
                 .body_json(&request)
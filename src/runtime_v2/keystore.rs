@@ -0,0 +1,171 @@
+use anyhow::Context;
+
+use super::driver::DriverInfo;
+use super::secrets::SecretKey;
+use super::storage::{KeyRecord, KeyStorage, SignatureRecord};
+use super::types::UserCtx;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    Ed25519,
+    Secp256k1,
+}
+
+impl KeyAlgorithm {
+    pub fn parse(algorithm: &str) -> anyhow::Result<Self> {
+        match algorithm {
+            "ed25519" => Ok(Self::Ed25519),
+            "secp256k1" => Ok(Self::Secp256k1),
+            _ => anyhow::bail!("Unknown key algorithm: {}", algorithm),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ed25519 => "ed25519",
+            Self::Secp256k1 => "secp256k1",
+        }
+    }
+
+    /// New key pair as `(public_key, private_key)`. Ed25519 public keys are 32 bytes, secp256k1
+    /// ones are compressed SEC1 points.
+    pub fn generate(&self) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Self::Ed25519 => {
+                let mut secret = [0; 32];
+                rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+                let key = ed25519_dalek::SigningKey::from_bytes(&secret);
+                (key.verifying_key().to_bytes().to_vec(), secret.to_vec())
+            }
+            Self::Secp256k1 => {
+                let key = k256::ecdsa::SigningKey::random(&mut rand::rngs::OsRng);
+                let public_key = key.verifying_key().to_encoded_point(true);
+                (public_key.as_bytes().to_vec(), key.to_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Signature of `payload`, ed25519 or ECDSA over its sha256 as the 64 byte `r || s` with a
+    /// low `s`, so it verifies with [`super::crypto`].
+    pub fn sign(&self, private_key: &[u8], payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Ed25519 => {
+                use ed25519_dalek::Signer;
+
+                let secret: &[u8; 32] = private_key
+                    .try_into()
+                    .context("Invalid ed25519 private key")?;
+                let key = ed25519_dalek::SigningKey::from_bytes(secret);
+                Ok(key.sign(payload).to_bytes().to_vec())
+            }
+            Self::Secp256k1 => {
+                use k256::ecdsa::signature::Signer;
+
+                let key = k256::ecdsa::SigningKey::from_slice(private_key)
+                    .context("Invalid secp256k1 private key")?;
+                let signature: k256::ecdsa::Signature = key.sign(payload);
+                Ok(signature.to_bytes().to_vec())
+            }
+        }
+    }
+}
+
+/// Private keys are bound to the driver, name and version they are provisioned for.
+pub fn key_aad(driver_name: &str, key_name: &str, version: u32) -> String {
+    format!("{}/{}@{}", driver_name, key_name, version)
+}
+
+/// Keys of a single driver, handed to its instances. Signs with the latest version of a key
+/// without giving out its private key, and records every signature it makes.
+#[derive(Clone)]
+pub struct Keyring {
+    driver_info: DriverInfo,
+    store: Box<dyn KeyStorage>,
+    secret_key: Option<SecretKey>,
+}
+
+impl Keyring {
+    pub fn new(
+        driver_info: DriverInfo,
+        store: Box<dyn KeyStorage>,
+        secret_key: Option<SecretKey>,
+    ) -> Self {
+        Self {
+            driver_info,
+            store,
+            secret_key,
+        }
+    }
+
+    /// Latest version of the key `name`, its private key is still sealed.
+    pub async fn key(&self, name: &str) -> anyhow::Result<Option<KeyRecord>> {
+        self.store.latest(&self.driver_info.name, name).await
+    }
+
+    /// Signature of `payload` with the latest version of the key `name`, along with that version.
+    pub async fn sign(
+        &self,
+        ctx: &UserCtx,
+        name: &str,
+        payload: &[u8],
+    ) -> anyhow::Result<Option<(u32, Vec<u8>)>> {
+        let Some(key) = self.key(name).await? else {
+            return Ok(None);
+        };
+        let secret_key = self
+            .secret_key
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No secret key to decrypt {} with", name))?;
+        let private_key = secret_key.open(
+            key_aad(&key.driver_name, &key.key_name, key.version).as_bytes(),
+            &key.private_key,
+        )?;
+        let signature = key.algorithm.sign(&private_key, payload)?;
+
+        // a signature that can't be accounted for is not handed out
+        self.store
+            .record_signature(&SignatureRecord {
+                driver_name: self.driver_info.name.clone(),
+                driver_version: self.driver_info.version.clone(),
+                key_name: key.key_name,
+                key_version: key.version,
+                user_id: ctx.user_id.clone(),
+                execution_id: ctx.execution_id.clone(),
+                payload_hash: hex::encode(super::crypto::sha256(payload)),
+                signed_at: unix_now(),
+            })
+            .await
+            .context("Failed to record signature")?;
+
+        Ok(Some((key.version, signature)))
+    }
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_v2::crypto;
+
+    #[test]
+    fn test_sign_verifies() {
+        for algorithm in [KeyAlgorithm::Ed25519, KeyAlgorithm::Secp256k1] {
+            let (public_key, private_key) = algorithm.generate();
+            let signature = algorithm.sign(&private_key, b"transfer").unwrap();
+
+            let verify = match algorithm {
+                KeyAlgorithm::Ed25519 => crypto::verify_ed25519,
+                KeyAlgorithm::Secp256k1 => crypto::verify_secp256k1,
+            };
+            assert_eq!(verify(&public_key, b"transfer", &signature), Ok(true));
+            assert_eq!(verify(&public_key, b"forged", &signature), Ok(false));
+            assert_eq!(KeyAlgorithm::parse(algorithm.as_str()).unwrap(), algorithm);
+        }
+    }
+}
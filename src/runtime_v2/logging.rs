@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use tracing::Level;
+
+use super::driver::DriverInfo;
+use super::quota::RateLimiter;
+use super::types::{LoggingConfig, UserCtx};
+
+/// Target of the events guests log, so they can be filtered apart from the runtime's own.
+pub const TARGET: &str = "units::guest";
+
+macro_rules! emit {
+    ($level:expr, $($arg:tt)+) => {
+        match $level {
+            Level::TRACE => tracing::trace!(target: TARGET, $($arg)+),
+            Level::DEBUG => tracing::debug!(target: TARGET, $($arg)+),
+            Level::INFO => tracing::info!(target: TARGET, $($arg)+),
+            Level::WARN => tracing::warn!(target: TARGET, $($arg)+),
+            _ => tracing::error!(target: TARGET, $($arg)+),
+        }
+    };
+}
+
+/// Forwards what programs and drivers log to `tracing`, along with where it comes from, within
+/// the levels and rate the admin allows.
+#[derive(Clone)]
+pub struct GuestLogs {
+    level: Level,
+    levels: HashMap<String, Level>,
+    drivers: RateLimiter<DriverInfo>,
+    programs: RateLimiter<String>,
+}
+
+impl GuestLogs {
+    pub fn new(config: &LoggingConfig) -> anyhow::Result<Self> {
+        let parse = |level: &str| {
+            level
+                .parse::<Level>()
+                .map_err(|_| anyhow::anyhow!("Invalid log level: {:?}", level))
+        };
+
+        Ok(Self {
+            level: parse(&config.level)?,
+            levels: config
+                .levels
+                .iter()
+                .map(|(driver, level)| Ok((driver.clone(), parse(level)?)))
+                .collect::<anyhow::Result<_>>()?,
+            drivers: RateLimiter::new(config.logs_per_minute),
+            programs: RateLimiter::new(config.logs_per_minute),
+        })
+    }
+
+    pub fn driver(
+        &self,
+        ctx: &UserCtx,
+        driver_info: &DriverInfo,
+        path: Option<&str>,
+        level: Level,
+        message: &str,
+        fields: &[(String, String)],
+    ) {
+        let threshold = self.levels.get(&driver_info.name).unwrap_or(&self.level);
+        if level > *threshold || self.drivers.acquire(driver_info).is_err() {
+            return;
+        }
+
+        emit!(
+            level,
+            runtime = "driver",
+            driver = driver_info.name.as_str(),
+            version = driver_info.version.as_str(),
+            user = ctx.user_id.as_str(),
+            execution = ctx.execution_id.as_str(),
+            path,
+            fields = %fields_json(fields),
+            "{}",
+            message
+        );
+    }
+
+    pub fn program(&self, ctx: &UserCtx, level: Level, message: &str, fields: &[(String, String)]) {
+        if level > self.level || self.programs.acquire(&ctx.user_id).is_err() {
+            return;
+        }
+
+        emit!(
+            level,
+            runtime = "process",
+            user = ctx.user_id.as_str(),
+            execution = ctx.execution_id.as_str(),
+            fields = %fields_json(fields),
+            "{}",
+            message
+        );
+    }
+}
+
+fn fields_json(fields: &[(String, String)]) -> serde_json::Value {
+    serde_json::Value::Object(
+        fields
+            .iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let config = LoggingConfig {
+            level: "warn".to_string(),
+            levels: HashMap::from([("upi".to_string(), "DEBUG".to_string())]),
+            logs_per_minute: 0,
+        };
+        let logs = GuestLogs::new(&config).unwrap();
+
+        assert_eq!(logs.level, Level::WARN);
+        assert_eq!(logs.levels.get("upi"), Some(&Level::DEBUG));
+
+        let config = LoggingConfig {
+            level: "loud".to_string(),
+            ..LoggingConfig::default()
+        };
+        assert!(GuestLogs::new(&config).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+use super::sandbox::HttpAllowlist;
+use crate::service::proto_types;
+
+/// Operations a driver can declare in its manifest.
+pub const OPERATIONS: &[&str] = &[
+    "intend",
+    "done",
+    "transfer",
+    "transfer-v2",
+    "view",
+    "bind",
+    "two-phase",
+    "on-callback",
+];
+
+/// Metadata a driver is loaded with, what it does and what it needs from the platform.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DriverManifest {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub schemas: Schemas,
+    /// Operations the driver supports, empty when the driver doesn't say.
+    #[serde(default)]
+    pub operations: Vec<String>,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+}
+
+/// JSON schemas of the values a driver accepts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schemas {
+    pub account_info: Option<serde_json::Value>,
+    pub bind_input: Option<serde_json::Value>,
+    pub transfer_value: Option<serde_json::Value>,
+}
+
+/// What the driver asks to reach outside of itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub http_hosts: Vec<String>,
+    #[serde(default)]
+    pub storage_prefixes: Vec<String>,
+}
+
+impl DriverManifest {
+    /// Whether the driver supports `operation`, a manifest without operations supports all.
+    pub fn supports(&self, operation: &str) -> bool {
+        self.operations.is_empty() || self.operations.iter().any(|op| op == operation)
+    }
+
+    /// Whether the driver can be called for `call`, an export or a step of one. `transfer-v2`
+    /// reaches drivers without typed values through their `transfer`.
+    pub fn allows(&self, call: &str) -> bool {
+        match call {
+            "prepare" | "commit" | "abort" => self.supports("two-phase"),
+            "transfer-v2" => self.supports("transfer-v2") || self.supports("transfer"),
+            operation => self.supports(operation),
+        }
+    }
+
+    /// Checks account info the driver returned against the schema it declared for it.
+    pub fn validate_account_info(&self, account_info: &str) -> Result<(), String> {
+        let Some(schema) = &self.schemas.account_info else {
+            return Ok(());
+        };
+
+        let account_info: serde_json::Value = serde_json::from_str(account_info)
+            .map_err(|e| format!("Account info is not json: {}", e))?;
+        validate(schema, &account_info)
+    }
+
+    /// Checks the value of a transfer against the schema the driver declared for it.
+    pub fn validate_transfer_value(&self, value: &serde_json::Value) -> Result<(), String> {
+        match &self.schemas.transfer_value {
+            Some(schema) => validate(schema, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks `input` of a bind against the schema the driver declared for it.
+    pub fn validate_bind_input(&self, input: &str) -> Result<(), String> {
+        let Some(schema) = &self.schemas.bind_input else {
+            return Ok(());
+        };
+
+        let input: serde_json::Value =
+            serde_json::from_str(input).map_err(|e| format!("Bind input is not json: {}", e))?;
+        validate(schema, &input)
+    }
+}
+
+fn validate(schema: &serde_json::Value, instance: &serde_json::Value) -> Result<(), String> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+
+    let errors = validator
+        .iter_errors(instance)
+        .map(|error| format!("{}: {}", error.instance_path, error))
+        .collect::<Vec<_>>();
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("; ")),
+    }
+}
+
+fn parse_schema(field: &'static str, schema: String) -> crate::Result<Option<serde_json::Value>> {
+    if schema.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let schema: serde_json::Value =
+        serde_json::from_str(&schema).map_err(|e| crate::Error::invalid_argument(field, e))?;
+    jsonschema::validator_for(&schema).map_err(|e| crate::Error::invalid_argument(field, e))?;
+
+    Ok(Some(schema))
+}
+
+impl TryFrom<proto_types::DriverManifest> for DriverManifest {
+    type Error = crate::Error;
+
+    fn try_from(manifest: proto_types::DriverManifest) -> crate::Result<Self> {
+        if let Some(operation) = manifest
+            .operations
+            .iter()
+            .find(|op| !OPERATIONS.contains(&op.as_str()))
+        {
+            return Err(crate::Error::invalid_argument(
+                "manifest.operations",
+                format!("Unknown operation: {}", operation),
+            ));
+        }
+
+        let capabilities = manifest.capabilities.unwrap_or_default();
+        HttpAllowlist::parse(&capabilities.http_hosts)
+            .map_err(|e| crate::Error::invalid_argument("manifest.capabilities.http_hosts", e))?;
+
+        Ok(Self {
+            description: manifest.description,
+            schemas: Schemas {
+                account_info: parse_schema(
+                    "manifest.account_info_schema",
+                    manifest.account_info_schema,
+                )?,
+                bind_input: parse_schema("manifest.bind_input_schema", manifest.bind_input_schema)?,
+                transfer_value: parse_schema(
+                    "manifest.transfer_value_schema",
+                    manifest.transfer_value_schema,
+                )?,
+            },
+            operations: manifest.operations,
+            capabilities: Capabilities {
+                http_hosts: capabilities.http_hosts,
+                storage_prefixes: capabilities.storage_prefixes,
+            },
+        })
+    }
+}
+
+impl From<DriverManifest> for proto_types::DriverManifest {
+    fn from(manifest: DriverManifest) -> Self {
+        let schema = |schema: Option<serde_json::Value>| {
+            schema.map(|schema| schema.to_string()).unwrap_or_default()
+        };
+
+        Self {
+            description: manifest.description,
+            account_info_schema: schema(manifest.schemas.account_info),
+            bind_input_schema: schema(manifest.schemas.bind_input),
+            transfer_value_schema: schema(manifest.schemas.transfer_value),
+            operations: manifest.operations,
+            capabilities: Some(proto_types::DriverCapabilities {
+                http_hosts: manifest.capabilities.http_hosts,
+                storage_prefixes: manifest.capabilities.storage_prefixes,
+            }),
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use crate::runtime_v2::integration;
 use dyn_clone::DynClone;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+pub mod clock;
+pub mod kv;
+pub mod outbox;
+pub mod random;
+pub mod route;
 pub mod users;
 
 use super::types;
@@ -14,108 +17,183 @@ impl Persistance for () {}
 #[derive(Clone)]
 pub struct Platform {
     pub storage: Storage,
+    /// Settlements of the keys routed to settlement integrations.
+    pub outbox: Box<dyn outbox::Outbox>,
     // pub persistance: Box<dyn Persistance>
+    pinned_clock: Option<types::PinnedClock>,
+    random_seed: Option<u64>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Storage {
-    pub redis: Arc<Mutex<redis::Client>>,
-    pub kev: Arc<RwLock<HashMap<String, String>>>,
+    routes: route::Routes,
 }
 
 impl Platform {
-    pub fn init(_config: types::PlatformConfig) -> anyhow::Result<Self> {
+    pub async fn init(config: types::PlatformConfig) -> anyhow::Result<Self> {
+        Self::with_integrations(config, route::Integrations::builtin()).await
+    }
+
+    /// Platform whose settlement routes can name the integrations of `integrations`.
+    pub async fn with_integrations(
+        config: types::PlatformConfig,
+        integrations: route::Integrations,
+    ) -> anyhow::Result<Self> {
         tracing::debug!("Initializing platform");
+        if config.clock.is_some() || config.random_seed.is_some() {
+            tracing::warn!("Clock or randomness of guests is pinned, this is meant for tests");
+        }
+        let outbox = outbox::connect(&config.outbox).await?;
+        let routes = route::Routes::build(
+            kv::connect(&config.storage).await?,
+            outbox.as_ref(),
+            &integrations,
+            &config.routes,
+        )
+        .await?;
+        // settlements left pending by a previous run are picked up like new ones
+        tokio::spawn(outbox::Worker::new(outbox.clone(), integrations, config.outbox).run());
+
         Ok(Self {
-            storage: Storage {
-                redis: Arc::new(Mutex::new(redis::Client::open("redis://127.0.0.1/")?)),
-                kev: Arc::new(RwLock::new(HashMap::new())),
-            },
+            storage: Storage { routes },
+            outbox,
             // persistance: Box::new(()),
+            pinned_clock: config.clock,
+            random_seed: config.random_seed,
         })
     }
+
+    /// Clock of a new execution, pinned ones start over for every execution.
+    pub fn clock(&self) -> clock::Clock {
+        match &self.pinned_clock {
+            Some(pinned) => clock::Clock::pinned(
+                Duration::from_secs(pinned.start),
+                Duration::from_millis(pinned.step_ms),
+            ),
+            None => clock::Clock::System,
+        }
+    }
+
+    /// Randomness of a new execution, seeded ones start over for every execution.
+    pub fn random(&self) -> random::Random {
+        match self.random_seed {
+            Some(seed) => random::Random::seeded(seed),
+            None => random::Random::System,
+        }
+    }
+}
+
+/// Part of a key after its namespace, what the driver wrote. Keys are routed on it so the
+/// namespace doesn't change where a key is stored.
+fn local_key(key: &str) -> &str {
+    key.split_once('/').map(|(_, local)| local).unwrap_or(key)
+}
+
+/// Keys returned by a single [`Storage::list`].
+pub const LIST_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KvError {
+    /// The stored value can't be updated the way it was asked to.
+    #[error("{0}")]
+    InvalidValue(String),
+    /// The route of the key doesn't allow it to be changed that way.
+    #[error("{0}")]
+    Denied(String),
+    #[error(transparent)]
+    Backend(#[from] anyhow::Error),
 }
 
 impl Storage {
-    pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
-        match key {
-            key if key.starts_with("sol:") => self.get_sol(key),
-            key => self.get_redis(key),
+    pub fn new(backend: Box<dyn kv::KvBackend>) -> Self {
+        Self {
+            routes: route::Routes::new(backend),
         }
     }
 
-    pub fn get_sol(&self, key: &str) -> anyhow::Result<Option<String>> {
-        self.kev
-            .read()
-            .map(|x| x.get(key).cloned())
-            .map_err(|e| anyhow::anyhow!("Error reading storage: {:?}", e))
+    pub async fn get(&self, key: &str) -> Result<Option<String>, KvError> {
+        Ok(self.routes.route(key).get(key).await?)
     }
 
-    pub fn get_redis(&self, key: &str) -> anyhow::Result<Option<String>> {
-        let client = self
-            .redis
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Error getting redis client: {:?}", e))?;
-        let mut con = client.get_connection()?;
-        let _ = redis::cmd("GET").arg(key).query::<Option<String>>(&mut con);
-
-        self.kev
-            .read()
-            .map(|x| x.get(key).cloned())
-            .map_err(|e| anyhow::anyhow!("Error reading storage: {:?}", e))
+    pub async fn set(&self, key: &str, value: &str) -> Result<(), KvError> {
+        self.routes.route(key).set(key, value).await
     }
 
-    pub fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
-        match key {
-            key if key.starts_with("sol:") => self.set_sol(key, value),
-            key => self.set_redis(key, value),
-        }
+    pub async fn delete(&self, key: &str) -> Result<(), KvError> {
+        self.routes.route(key).delete(key).await
     }
 
-    pub fn set_redis(&self, key: &str, value: &str) -> anyhow::Result<()> {
-        let client = self
-            .redis
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Error getting redis client: {:?}", e))?;
-        let mut con = client.get_connection()?;
-        redis::cmd("SET").arg(key).arg(value).exec(&mut con)?;
-
-        self.kev
-            .write()
-            .map(|mut x| {
-                x.insert(key.to_string(), value.to_string());
-            })
-            .map_err(|e| anyhow::anyhow!("Error writing to storage: {:?}", e))
+    /// Keys starting with `prefix`, in order, after `cursor` when there is one. Along with the
+    /// keys comes the cursor of the next page, if there is more. Keys are listed from the store
+    /// of the route `prefix` falls in.
+    pub async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<String>, Option<String>), KvError> {
+        let mut keys = self
+            .routes
+            .route(prefix)
+            .store
+            .list(prefix, cursor, LIST_PAGE_SIZE + 1)
+            .await?;
+        let next = match keys.len() > LIST_PAGE_SIZE {
+            true => {
+                keys.truncate(LIST_PAGE_SIZE);
+                keys.last().cloned()
+            }
+            false => None,
+        };
+        Ok((keys, next))
     }
 
-    pub fn set_sol(&self, key: &str, value: &str) -> anyhow::Result<()> {
-        tracing::info!(?key, ?value, "submitting proof");
-        let output = integration::solana::transfer_token(key.to_string(), value.to_string());
-        tracing::info!(signature = ?output, "triggering solana transfer");
-
-        self.kev
-            .write()
-            .map(|mut x| {
-                x.insert(key.to_string(), value.to_string());
-            })
-            .map_err(|e| anyhow::anyhow!("Error writing to storage: {:?}", e))
+    /// Sets `key` to `new` if its value is `expected`, `None` expecting it not to be set.
+    /// Returns whether it was set.
+    pub async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, KvError> {
+        self.routes
+            .route(key)
+            .compare_and_swap(key, expected, new)
+            .await
     }
 
-    pub fn delete(&self, key: &str) -> anyhow::Result<()> {
-        {
-            let client = self
-                .redis
-                .lock()
-                .map_err(|e| anyhow::anyhow!("Error getting redis client: {:?}", e))?;
-            let mut con = client.get_connection()?;
-            redis::cmd("DEL").arg(key).exec(&mut con)?;
-        }
+    /// Adds `delta` to the integer stored at `key`, a key that isn't set counts as `0`.
+    /// Returns the new value.
+    pub async fn increment(&self, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.routes.route(key).increment(key, delta).await
+    }
+}
 
-        self.kev
-            .write()
-            .map(|mut x| {
-                x.remove(key);
-            })
-            .map_err(|e| anyhow::anyhow!("Error deleting from storage: {:?}", e))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_list() {
+        let storage = Storage::new(Box::new(kv::MemoryBackend::default()));
+        for i in 0..150 {
+            storage
+                .set(&format!("ledger/acc:{:03}", i), &i.to_string())
+                .await
+                .unwrap();
+        }
+        storage.set("other/acc:000", "").await.unwrap();
+
+        let (keys, cursor) = storage.list("ledger/acc:", None).await.unwrap();
+        assert_eq!(keys.len(), LIST_PAGE_SIZE);
+        assert_eq!(keys[0], "ledger/acc:000");
+        assert_eq!(cursor.as_deref(), Some("ledger/acc:099"));
+
+        let (keys, cursor) = storage
+            .list("ledger/acc:", cursor.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 50);
+        assert_eq!(keys[0], "ledger/acc:100");
+        assert_eq!(cursor, None);
     }
 }
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Time handed to guests, through the `clock` interface and `wasi:clocks`. A pinned clock starts
+/// at a fixed time and only moves by `step` on every reading, so an execution sees the same
+/// times whenever it is replayed.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    System,
+    Pinned {
+        start: Duration,
+        step: Duration,
+        readings: Arc<AtomicU64>,
+    },
+}
+
+impl Clock {
+    pub fn pinned(start: Duration, step: Duration) -> Self {
+        Self::Pinned {
+            start,
+            step,
+            readings: Arc::default(),
+        }
+    }
+
+    /// Time since the unix epoch.
+    pub fn now(&self) -> Duration {
+        match self {
+            Self::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            Self::Pinned { start, .. } => *start + self.advance(),
+        }
+    }
+
+    /// Nanoseconds since an arbitrary point, only meaningful against other readings.
+    pub fn monotonic(&self) -> u64 {
+        match self {
+            Self::System => STARTED.elapsed().as_nanos() as u64,
+            Self::Pinned { .. } => self.advance().as_nanos() as u64,
+        }
+    }
+
+    /// Time a pinned clock moved by before this reading.
+    fn advance(&self) -> Duration {
+        match self {
+            Self::System => Duration::ZERO,
+            Self::Pinned { step, readings, .. } => {
+                let reading = readings.fetch_add(1, Ordering::Relaxed);
+                Duration::from_nanos((step.as_nanos() as u64).saturating_mul(reading))
+            }
+        }
+    }
+}
+
+impl wasmtime_wasi::HostWallClock for Clock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        Clock::now(self)
+    }
+}
+
+impl wasmtime_wasi::HostMonotonicClock for Clock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.monotonic()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned_clock() {
+        let clock = Clock::pinned(
+            Duration::from_secs(1_735_689_600),
+            Duration::from_millis(10),
+        );
+        let shared = clock.clone();
+
+        assert_eq!(clock.now(), Duration::from_secs(1_735_689_600));
+        assert_eq!(shared.monotonic(), 10_000_000);
+        assert_eq!(
+            clock.now(),
+            Duration::from_secs(1_735_689_600) + Duration::from_millis(20)
+        );
+
+        let still = Clock::pinned(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(still.now(), still.now());
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+use tonic::async_trait;
+
+use super::KvError;
+use crate::runtime_v2::types::KvConfig;
+
+mod redis_backend;
+mod sql;
+
+pub use redis_backend::RedisBackend;
+pub use sql::SqliteBackend;
+
+/// Where the platform storage keeps its keys. Every operation is atomic on its own, backends
+/// that can't increment a key atomically fall back on [`KvBackend::compare_and_swap`].
+#[async_trait]
+pub trait KvBackend: dyn_clone::DynClone + Send + Sync + 'static {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
+    async fn set(&self, key: &str, value: &str) -> anyhow::Result<()>;
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+    /// Up to `limit` keys starting with `prefix` and coming after `cursor`, in order.
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>>;
+    /// Sets `key` to `new` if its value is `expected`, `None` expecting it not to be set.
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> anyhow::Result<bool>;
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64, KvError> {
+        // a swap only fails when another write went through, so this always makes progress
+        loop {
+            let current = self.get(key).await?;
+            let value = add(key, current.as_deref(), delta)?;
+            if self
+                .compare_and_swap(key, current.as_deref(), &value.to_string())
+                .await?
+            {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+dyn_clone::clone_trait_object!(KvBackend);
+
+pub async fn connect(config: &KvConfig) -> anyhow::Result<Box<dyn KvBackend>> {
+    tracing::debug!(?config, "Connecting platform storage");
+    match config {
+        KvConfig::Memory => Ok(Box::new(MemoryBackend::default())),
+        KvConfig::Sqlite { url } => Ok(Box::new(SqliteBackend::new(url).await?)),
+        KvConfig::Redis(config) => Ok(Box::new(RedisBackend::new(config).await?)),
+    }
+}
+
+/// Counter stored at `key` once `delta` is added to its `current` value.
+pub(super) fn add(key: &str, current: Option<&str>, delta: i64) -> Result<i64, KvError> {
+    let current = match current {
+        Some(current) => current
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| KvError::InvalidValue(format!("Value of {} is not an integer", key)))?,
+        None => 0,
+    };
+    current
+        .checked_add(delta)
+        .ok_or_else(|| KvError::InvalidValue(format!("Incrementing {} overflows", key)))
+}
+
+/// Keys kept in memory, lost on restart.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    map: Arc<RwLock<BTreeMap<String, String>>>,
+}
+
+#[async_trait]
+impl KvBackend for MemoryBackend {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .map
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .get(key)
+            .cloned())
+    }
+
+    async fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.map
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.map
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .remove(key);
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let start = match cursor {
+            Some(cursor) if cursor >= prefix => Bound::Excluded(cursor.to_string()),
+            _ => Bound::Included(prefix.to_string()),
+        };
+        Ok(self
+            .map
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .range((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> anyhow::Result<bool> {
+        let mut map = self
+            .map
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?;
+        if map.get(key).map(String::as_str) != expected {
+            return Ok(false);
+        }
+        map.insert(key.to_string(), new.to_string());
+        Ok(true)
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64, KvError> {
+        let mut map = self
+            .map
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?;
+        let value = add(key, map.get(key).map(String::as_str), delta)?;
+        map.insert(key.to_string(), value.to_string());
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        let kv = MemoryBackend::default();
+
+        assert!(kv.compare_and_swap("a/x", None, "1").await.unwrap());
+        assert!(!kv.compare_and_swap("a/x", None, "2").await.unwrap());
+        assert!(!kv.compare_and_swap("a/x", Some("2"), "3").await.unwrap());
+        assert!(kv.compare_and_swap("a/x", Some("1"), "3").await.unwrap());
+        assert_eq!(kv.get("a/x").await.unwrap().as_deref(), Some("3"));
+
+        assert_eq!(kv.increment("a/x", 4).await.unwrap(), 7);
+        assert_eq!(kv.increment("a/y", -2).await.unwrap(), -2);
+        kv.set("a/z", "text").await.unwrap();
+        assert!(matches!(
+            kv.increment("a/z", 1).await,
+            Err(KvError::InvalidValue(_))
+        ));
+
+        kv.set("b/x", "").await.unwrap();
+        assert_eq!(kv.list("a/", None, 2).await.unwrap(), ["a/x", "a/y"]);
+        assert_eq!(kv.list("a/", Some("a/y"), 2).await.unwrap(), ["a/z"]);
+        kv.delete("a/y").await.unwrap();
+        assert_eq!(kv.list("a/", None, 10).await.unwrap(), ["a/x", "a/z"]);
+    }
+}
@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::AsyncCommands;
+use tonic::async_trait;
+
+use super::KvBackend;
+use crate::runtime_v2::platform::KvError;
+use crate::runtime_v2::types::RedisConfig;
+
+/// Sets `KEYS[1]` to `ARGV[3]` if its value is `ARGV[2]`, or if it isn't set when `ARGV[1]` is
+/// `0`, and adds it to the index `KEYS[2]`. Scripts run atomically.
+const COMPARE_AND_SWAP: &str = r#"
+local current = redis.call('GET', KEYS[1])
+local matches
+if ARGV[1] == '1' then
+    matches = current == ARGV[2]
+else
+    matches = current == false
+end
+if matches then
+    redis.call('SET', KEYS[1], ARGV[3])
+    redis.call('ZADD', KEYS[2], 0, KEYS[1])
+    return 1
+end
+return 0
+"#;
+
+/// Adds `ARGV[1]` to `KEYS[1]` and adds it to the index `KEYS[2]`.
+const INCREMENT: &str = r#"
+local value = redis.call('INCRBY', KEYS[1], ARGV[1])
+redis.call('ZADD', KEYS[2], 0, KEYS[1])
+return value
+"#;
+
+/// Delay before the first retry of a command, doubled on every retry after it.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Keys kept in redis, over a single multiplexed connection shared by every execution and
+/// re-established in the background when it drops.
+#[derive(Clone)]
+pub struct RedisBackend {
+    connection: ConnectionManager,
+    retries: u32,
+}
+
+impl RedisBackend {
+    /// Fails when redis can't be reached, so a misconfigured server doesn't start.
+    pub async fn new(config: &RedisConfig) -> Result<Self> {
+        let client = redis::Client::open(config.url.as_str())?;
+        let connection = client
+            .get_connection_manager_with_config(
+                ConnectionManagerConfig::new()
+                    .set_connection_timeout(Duration::from_millis(config.connect_timeout_ms))
+                    .set_response_timeout(Duration::from_millis(config.response_timeout_ms))
+                    .set_number_of_retries(config.retries as usize),
+            )
+            .await
+            .with_context(|| format!("Failed to connect to redis at {}", config.url))?;
+
+        Ok(Self {
+            connection,
+            retries: config.retries,
+        })
+    }
+
+    /// Runs `command`, again when it failed before reaching redis or before its answer came
+    /// back. Only commands that can be applied twice are retried: a swap or an increment that
+    /// went through but whose answer was lost would be applied again.
+    async fn retry<T, F, Fut>(&self, command: F) -> redis::RedisResult<T>
+    where
+        F: Fn(ConnectionManager) -> Fut,
+        Fut: Future<Output = redis::RedisResult<T>>,
+    {
+        let mut delay = RETRY_DELAY;
+        for _ in 0..self.retries {
+            match command(self.connection.clone()).await {
+                Err(e) if e.is_io_error() || e.is_timeout() || e.is_connection_dropped() => {
+                    tracing::warn!(error = %e, ?delay, "Retrying redis command");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        command(self.connection.clone()).await
+    }
+}
+
+/// Sorted set the keys of the namespace of `key` are indexed in, with a score of 0 so they are
+/// ordered by name and can be paged through with ZRANGEBYLEX. Stored keys always have a
+/// namespace, so they never take the name of an index.
+fn index_key(key: &str) -> String {
+    let namespace = key.split_once('/').map_or("", |(namespace, _)| namespace);
+    format!("units:index:{}", namespace)
+}
+
+/// ZRANGEBYLEX bounds of the keys starting with `prefix` that come after `cursor`. No key goes
+/// past `prefix` followed by 0xff, a byte UTF-8 never uses.
+fn lex_range(prefix: &str, cursor: Option<&str>) -> (Vec<u8>, Vec<u8>) {
+    let min = match cursor {
+        Some(cursor) if cursor >= prefix => [&b"("[..], cursor.as_bytes()].concat(),
+        _ => [&b"["[..], prefix.as_bytes()].concat(),
+    };
+    let max = [&b"["[..], prefix.as_bytes(), &b"\xff"[..]].concat();
+    (min, max)
+}
+
+#[async_trait]
+impl KvBackend for RedisBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .retry(|mut con| async move { con.get(key).await })
+            .await?)
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        let index = index_key(key);
+        let index = index.as_str();
+        Ok(self
+            .retry(|mut con| async move {
+                redis::pipe()
+                    .atomic()
+                    .set(key, value)
+                    .ignore()
+                    .zadd(index, key, 0)
+                    .ignore()
+                    .query_async(&mut con)
+                    .await
+            })
+            .await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let index = index_key(key);
+        let index = index.as_str();
+        Ok(self
+            .retry(|mut con| async move {
+                redis::pipe()
+                    .atomic()
+                    .del(key)
+                    .ignore()
+                    .zrem(index, key)
+                    .ignore()
+                    .query_async(&mut con)
+                    .await
+            })
+            .await?)
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<&str>, limit: usize) -> Result<Vec<String>> {
+        let index = index_key(prefix);
+        let index = index.as_str();
+        let (min, max) = lex_range(prefix, cursor);
+        let (min, max) = (min.as_slice(), max.as_slice());
+        Ok(self
+            .retry(|mut con| async move {
+                con.zrangebylex_limit(index, min, max, 0, limit as isize)
+                    .await
+            })
+            .await?)
+    }
+
+    async fn compare_and_swap(&self, key: &str, expected: Option<&str>, new: &str) -> Result<bool> {
+        let swapped: i64 = redis::Script::new(COMPARE_AND_SWAP)
+            .key(key)
+            .key(index_key(key))
+            .arg(if expected.is_some() { "1" } else { "0" })
+            .arg(expected.unwrap_or_default())
+            .arg(new)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        Ok(swapped == 1)
+    }
+
+    async fn increment(&self, key: &str, delta: i64) -> Result<i64, KvError> {
+        let incremented: redis::RedisResult<i64> = redis::Script::new(INCREMENT)
+            .key(key)
+            .key(index_key(key))
+            .arg(delta)
+            .invoke_async(&mut self.connection.clone())
+            .await;
+        match incremented {
+            Ok(value) => Ok(value),
+            // INCRBY refuses values that aren't integers and results that overflow
+            Err(e) if e.kind() == redis::ErrorKind::ResponseError => Err(KvError::InvalidValue(
+                format!("Can't increment {}: {}", key, e),
+            )),
+            Err(e) => Err(KvError::Backend(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lex_range() {
+        assert_eq!(index_key("ledger/acc:alice"), "units:index:ledger");
+        assert_eq!(
+            lex_range("ledger/acc:", None),
+            (b"[ledger/acc:".to_vec(), b"[ledger/acc:\xff".to_vec())
+        );
+        assert_eq!(
+            lex_range("ledger/acc:", Some("ledger/acc:bob")).0,
+            b"(ledger/acc:bob".to_vec()
+        );
+        // cursors before the prefix start from it
+        assert_eq!(
+            lex_range("ledger/acc:", Some("ledger/a")).0,
+            b"[ledger/acc:".to_vec()
+        );
+    }
+}
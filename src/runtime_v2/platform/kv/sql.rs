@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+use tonic::async_trait;
+
+use super::KvBackend;
+
+/// Keys kept in the `KeyValue` table of a SQLite database.
+#[derive(Clone, Debug)]
+pub struct SqliteBackend {
+    pool: SqlitePool,
+}
+
+impl SqliteBackend {
+    pub async fn new(url: &str) -> Result<Self> {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect(url)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    pub fn from_pool(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl KvBackend for SqliteBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let row = sqlx::query!("SELECT value FROM KeyValue WHERE key = ?", key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.value))
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query!(
+            "INSERT OR REPLACE INTO KeyValue (key, value) VALUES (?, ?)",
+            key,
+            value
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to write key")?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        sqlx::query!("DELETE FROM KeyValue WHERE key = ?", key)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete key")?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<&str>, limit: usize) -> Result<Vec<String>> {
+        // keys starting with `prefix` sort between it and `prefix` followed by the last char,
+        // which unlike LIKE needs no escaping and goes through the primary key
+        let end = format!("{}{}", prefix, char::MAX);
+        let limit = limit as i64;
+        let rows = sqlx::query!(
+            "SELECT key FROM KeyValue WHERE key >= ? AND key < ? AND (? IS NULL OR key > ?) ORDER BY key LIMIT ?",
+            prefix,
+            end,
+            cursor,
+            cursor,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.key).collect())
+    }
+
+    async fn compare_and_swap(&self, key: &str, expected: Option<&str>, new: &str) -> Result<bool> {
+        let result = match expected {
+            Some(expected) => {
+                sqlx::query!(
+                    "UPDATE KeyValue SET value = ? WHERE key = ? AND value = ?",
+                    new,
+                    key,
+                    expected
+                )
+                .execute(&self.pool)
+                .await
+            }
+            None => {
+                sqlx::query!(
+                    "INSERT OR IGNORE INTO KeyValue (key, value) VALUES (?, ?)",
+                    key,
+                    new
+                )
+                .execute(&self.pool)
+                .await
+            }
+        }
+        .context("Failed to swap key")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
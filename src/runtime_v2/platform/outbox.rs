@@ -0,0 +1,555 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tonic::async_trait;
+
+use super::kv::{KvBackend, MemoryBackend};
+use super::route;
+use crate::runtime_v2::keystore::unix_now;
+use crate::runtime_v2::types::OutboxConfig;
+
+mod sql;
+
+pub use sql::SqliteOutbox;
+
+/// Settlements handed to the worker at once.
+const BATCH_SIZE: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettlementStatus {
+    /// Waiting for its next attempt.
+    Pending,
+    /// Claimed by a worker that is delivering it, until `next_attempt_at`. A worker that stops
+    /// before recording the outcome leaves it to be claimed again once that has passed.
+    InFlight,
+    Delivered,
+    /// Gave up on after `max_attempts`.
+    Failed,
+}
+
+impl SettlementStatus {
+    pub fn parse(status: &str) -> anyhow::Result<Self> {
+        match status {
+            "pending" => Ok(Self::Pending),
+            "in-flight" => Ok(Self::InFlight),
+            "delivered" => Ok(Self::Delivered),
+            "failed" => Ok(Self::Failed),
+            _ => anyhow::bail!("Unknown settlement status: {}", status),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::InFlight => "in-flight",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Value written to a key, to be delivered to a settlement integration.
+#[derive(Debug, Clone)]
+pub struct Settlement {
+    pub id: i64,
+    pub integration: String,
+    pub key: String,
+    pub value: String,
+    pub status: SettlementStatus,
+    /// Attempts made so far, counting the one in flight.
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    /// Reference the integration gave the delivery, a transaction signature.
+    pub signature: Option<String>,
+    /// Error of the last failed attempt.
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Settlement {
+    fn new(id: i64, integration: &str, key: &str, value: &str) -> Self {
+        let now = unix_now();
+        Self {
+            id,
+            integration: integration.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            status: SettlementStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            signature: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Settlements waiting to be delivered. The keys of settlement routes are kept next to them, so
+/// a value and its settlement are written together or not at all.
+#[async_trait]
+pub trait Outbox: dyn_clone::DynClone + Send + Sync + 'static {
+    /// Identity of the outbox, stable for as long as its settlements are kept. Along with the id
+    /// of a settlement it makes a key no settlement of another outbox has.
+    fn id(&self) -> &str;
+
+    /// Where the keys of settlement routes are kept.
+    fn store(&self) -> Box<dyn KvBackend>;
+
+    /// Sets `key` to `value` and queues its settlement, returns the id of the settlement.
+    async fn set(&self, integration: &str, key: &str, value: &str) -> anyhow::Result<i64>;
+
+    /// Sets `key` to `new` if its value is `expected` and queues its settlement, returns the id
+    /// of the settlement when it was set.
+    async fn compare_and_swap(
+        &self,
+        integration: &str,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> anyhow::Result<Option<i64>>;
+
+    async fn get(&self, id: i64) -> anyhow::Result<Option<Settlement>>;
+
+    /// Up to `limit` settlements, of `key` when there is one, most recent first.
+    async fn list(&self, key: Option<&str>, limit: u32) -> anyhow::Result<Vec<Settlement>>;
+
+    /// Claims up to `limit` settlements due by `now`, oldest first: the pending ones whose next
+    /// attempt is due and the in-flight ones whose lease ran out. They are marked in-flight until
+    /// `lease_until` and their attempts counted, so no other worker claims them meanwhile.
+    async fn claim(
+        &self,
+        now: u64,
+        lease_until: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Settlement>>;
+
+    /// Records the outcome of the attempt at delivering `settlement` it was claimed for, returns
+    /// false when it was claimed again since and the outcome was dropped.
+    async fn update(&self, settlement: &Settlement) -> anyhow::Result<bool>;
+}
+
+dyn_clone::clone_trait_object!(Outbox);
+
+pub async fn connect(config: &OutboxConfig) -> anyhow::Result<Box<dyn Outbox>> {
+    match &config.url {
+        Some(url) => Ok(Box::new(SqliteOutbox::new(url).await?)),
+        None => {
+            tracing::warn!("Settlements are kept in memory, the pending ones are lost on restart");
+            Ok(Box::new(MemoryOutbox::default()))
+        }
+    }
+}
+
+/// Settlements kept in memory, lost on restart along with the keys they settle. Writes hold the
+/// settlements locked until they are queued, so ids follow the order of the writes. Ids start
+/// over on restart, the outbox gets a new identity along with them.
+#[derive(Clone)]
+pub struct MemoryOutbox {
+    id: String,
+    store: MemoryBackend,
+    settlements: Arc<Mutex<Vec<Settlement>>>,
+}
+
+impl Default for MemoryOutbox {
+    fn default() -> Self {
+        Self {
+            id: crate::utils::id::new(),
+            store: MemoryBackend::default(),
+            settlements: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Outbox for MemoryOutbox {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn store(&self) -> Box<dyn KvBackend> {
+        Box::new(self.store.clone())
+    }
+
+    async fn set(&self, integration: &str, key: &str, value: &str) -> anyhow::Result<i64> {
+        let mut settlements = self.settlements.lock().await;
+        self.store.set(key, value).await?;
+        let id = settlements.len() as i64 + 1;
+        settlements.push(Settlement::new(id, integration, key, value));
+        Ok(id)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        integration: &str,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> anyhow::Result<Option<i64>> {
+        let mut settlements = self.settlements.lock().await;
+        if !self.store.compare_and_swap(key, expected, new).await? {
+            return Ok(None);
+        }
+        let id = settlements.len() as i64 + 1;
+        settlements.push(Settlement::new(id, integration, key, new));
+        Ok(Some(id))
+    }
+
+    async fn get(&self, id: i64) -> anyhow::Result<Option<Settlement>> {
+        Ok(self
+            .settlements
+            .lock()
+            .await
+            .iter()
+            .find(|settlement| settlement.id == id)
+            .cloned())
+    }
+
+    async fn list(&self, key: Option<&str>, limit: u32) -> anyhow::Result<Vec<Settlement>> {
+        Ok(self
+            .settlements
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .filter(|settlement| key.map_or(true, |key| settlement.key == key))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn claim(
+        &self,
+        now: u64,
+        lease_until: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Settlement>> {
+        Ok(self
+            .settlements
+            .lock()
+            .await
+            .iter_mut()
+            .filter(|settlement| {
+                matches!(
+                    settlement.status,
+                    SettlementStatus::Pending | SettlementStatus::InFlight
+                ) && settlement.next_attempt_at <= now
+            })
+            .take(limit as usize)
+            .map(|settlement| {
+                settlement.status = SettlementStatus::InFlight;
+                settlement.attempts += 1;
+                settlement.next_attempt_at = lease_until;
+                settlement.updated_at = now;
+                settlement.clone()
+            })
+            .collect())
+    }
+
+    async fn update(&self, settlement: &Settlement) -> anyhow::Result<bool> {
+        let mut settlements = self.settlements.lock().await;
+        match settlements.iter_mut().find(|current| {
+            current.id == settlement.id
+                && current.status == SettlementStatus::InFlight
+                && current.attempts == settlement.attempts
+        }) {
+            Some(current) => {
+                *current = settlement.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Delivers the settlements of an outbox in the background, retrying the ones that fail with a
+/// growing delay until `max_attempts`. Settlements are claimed before they are delivered, so
+/// workers of several servers can share an outbox, and handed to their integration along with
+/// `{outbox id}:{settlement id}` for it to drop the ones it already got from an attempt whose
+/// outcome was lost.
+pub struct Worker {
+    outbox: Box<dyn Outbox>,
+    integrations: route::Integrations,
+    config: OutboxConfig,
+}
+
+impl Worker {
+    pub fn new(
+        outbox: Box<dyn Outbox>,
+        integrations: route::Integrations,
+        config: OutboxConfig,
+    ) -> Self {
+        Self {
+            outbox,
+            integrations,
+            config,
+        }
+    }
+
+    pub async fn run(self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.poll_interval_ms.max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.deliver_due().await {
+                tracing::error!(error = ?e, "Failed to deliver settlements");
+            }
+        }
+    }
+
+    /// Attempts every settlement that is due, returns how many were attempted.
+    pub async fn deliver_due(&self) -> anyhow::Result<usize> {
+        let now = unix_now();
+        let due = self
+            .outbox
+            .claim(now, now + self.config.lease_secs, BATCH_SIZE)
+            .await?;
+        let attempted = due.len();
+        for settlement in due {
+            let id = settlement.id;
+            if let Err(e) = self.deliver(settlement).await {
+                tracing::error!(
+                    runtime = "platform",
+                    call = "settle",
+                    id,
+                    error = ?e,
+                    "Failed to record settlement"
+                );
+            }
+        }
+        Ok(attempted)
+    }
+
+    async fn deliver(&self, mut settlement: Settlement) -> anyhow::Result<()> {
+        let reference = format!("{}:{}", self.outbox.id(), settlement.id);
+        let result = match self.integrations.get(&settlement.integration) {
+            Ok(sink) => {
+                sink.write(&settlement.key, &settlement.value, Some(&reference))
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let now = unix_now();
+        settlement.updated_at = now;
+        match result {
+            Ok(signature) => {
+                tracing::info!(
+                    runtime = "platform",
+                    call = "settle",
+                    id = settlement.id,
+                    integration = settlement.integration.as_str(),
+                    key = settlement.key.as_str(),
+                    ?signature,
+                    "Delivered settlement"
+                );
+                settlement.status = SettlementStatus::Delivered;
+                settlement.signature = signature;
+                settlement.error = None;
+            }
+            Err(e) => {
+                settlement.error = Some(format!("{:#}", e));
+                if settlement.attempts >= self.config.max_attempts {
+                    settlement.status = SettlementStatus::Failed;
+                } else {
+                    settlement.status = SettlementStatus::Pending;
+                    settlement.next_attempt_at = now + self.retry_delay(settlement.attempts);
+                }
+                tracing::warn!(
+                    runtime = "platform",
+                    call = "settle",
+                    id = settlement.id,
+                    integration = settlement.integration.as_str(),
+                    key = settlement.key.as_str(),
+                    attempts = settlement.attempts,
+                    status = settlement.status.as_str(),
+                    error = ?e,
+                    "Failed to deliver settlement"
+                );
+            }
+        }
+        if !self.outbox.update(&settlement).await? {
+            tracing::warn!(
+                runtime = "platform",
+                call = "settle",
+                id = settlement.id,
+                attempts = settlement.attempts,
+                "Settlement was claimed again before its attempt was recorded"
+            );
+        }
+        Ok(())
+    }
+
+    /// Seconds to wait after the `attempts`th failed attempt.
+    fn retry_delay(&self, attempts: u32) -> u64 {
+        let factor = 1u64
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        self.config
+            .retry_delay_secs
+            .saturating_mul(factor)
+            .min(self.config.max_retry_delay_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_worker_retries() {
+        let outbox = MemoryOutbox::default();
+        let id = outbox
+            .set("unknown", "ledger/sol:alice", "10")
+            .await
+            .unwrap();
+        assert_eq!(
+            outbox
+                .store()
+                .get("ledger/sol:alice")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("10")
+        );
+        assert!(outbox
+            .compare_and_swap("unknown", "ledger/sol:alice", Some("9"), "11")
+            .await
+            .unwrap()
+            .is_none());
+
+        let worker = Worker::new(
+            Box::new(outbox.clone()),
+            route::Integrations::default(),
+            OutboxConfig {
+                max_attempts: 2,
+                retry_delay_secs: 0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+        let settlement = outbox.get(id).await.unwrap().unwrap();
+        assert_eq!(settlement.status, SettlementStatus::Pending);
+        assert_eq!(settlement.attempts, 1);
+        assert!(settlement.error.is_some());
+
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+        let settlement = outbox.get(id).await.unwrap().unwrap();
+        assert_eq!(settlement.status, SettlementStatus::Failed);
+        assert_eq!(worker.deliver_due().await.unwrap(), 0);
+
+        assert_eq!(
+            outbox
+                .list(Some("ledger/sol:alice"), 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    /// Integration answering with the idempotency key it was given.
+    struct Echo;
+
+    #[async_trait]
+    impl route::Sink for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        async fn write(
+            &self,
+            _key: &str,
+            _value: &str,
+            idempotency: Option<&str>,
+        ) -> anyhow::Result<Option<String>> {
+            Ok(idempotency.map(str::to_string))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_delivers() {
+        let outbox = MemoryOutbox::default();
+        let id = outbox.set("echo", "ledger/sol:alice", "10").await.unwrap();
+        let mut integrations = route::Integrations::default();
+        integrations.register(Arc::new(Echo));
+
+        let worker = Worker::new(
+            Box::new(outbox.clone()),
+            integrations,
+            OutboxConfig::default(),
+        );
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+        let settlement = outbox.get(id).await.unwrap().unwrap();
+        assert_eq!(settlement.status, SettlementStatus::Delivered);
+        assert_eq!(
+            settlement.signature,
+            Some(format!("{}:{}", outbox.id(), id))
+        );
+        // ids start over with another outbox, its keys don't
+        assert_ne!(MemoryOutbox::default().id(), outbox.id());
+        assert_eq!(worker.deliver_due().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let outbox = MemoryOutbox::default();
+        let id = outbox
+            .set("solana", "ledger/sol:alice", "10")
+            .await
+            .unwrap();
+        let now = unix_now();
+
+        let claimed = outbox.claim(now, now + 60, 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].status, SettlementStatus::InFlight);
+        assert_eq!(claimed[0].attempts, 1);
+        // claimed settlements aren't handed out again while their lease holds
+        assert!(outbox
+            .claim(now + 59, now + 119, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let reclaimed = outbox.claim(now + 60, now + 120, 10).await.unwrap();
+        assert_eq!(reclaimed.len(), 1);
+        assert_eq!(reclaimed[0].attempts, 2);
+
+        // the outcome of the attempt that lost its lease is dropped
+        let mut stale = claimed[0].clone();
+        stale.status = SettlementStatus::Delivered;
+        assert!(!outbox.update(&stale).await.unwrap());
+        let mut current = reclaimed[0].clone();
+        current.status = SettlementStatus::Delivered;
+        assert!(outbox.update(&current).await.unwrap());
+        assert_eq!(
+            outbox.get(id).await.unwrap().unwrap().status,
+            SettlementStatus::Delivered
+        );
+        assert!(outbox
+            .claim(now + 1000, now + 1060, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let worker = Worker::new(
+            Box::new(MemoryOutbox::default()),
+            route::Integrations::default(),
+            OutboxConfig {
+                retry_delay_secs: 5,
+                max_retry_delay_secs: 60,
+                ..Default::default()
+            },
+        );
+        assert_eq!(worker.retry_delay(1), 5);
+        assert_eq!(worker.retry_delay(3), 20);
+        assert_eq!(worker.retry_delay(10), 60);
+        assert_eq!(worker.retry_delay(100), 60);
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+use tonic::async_trait;
+
+use super::{Outbox, Settlement, SettlementStatus};
+use crate::runtime_v2::keystore::unix_now;
+use crate::runtime_v2::platform::kv::{KvBackend, SqliteBackend};
+
+/// Settlements kept in the `Outbox` table of a SQLite database, next to the `KeyValue` table the
+/// keys they settle are kept in.
+#[derive(Clone, Debug)]
+pub struct SqliteOutbox {
+    pool: SqlitePool,
+    /// Kept in the `OutboxIdentity` table, shared by every server using the database.
+    id: String,
+}
+
+struct OutboxRow {
+    id: i64,
+    integration: String,
+    key: String,
+    value: String,
+    status: String,
+    attempts: i64,
+    next_attempt_at: i64,
+    signature: Option<String>,
+    error: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl TryFrom<OutboxRow> for Settlement {
+    type Error = anyhow::Error;
+
+    fn try_from(row: OutboxRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            integration: row.integration,
+            key: row.key,
+            value: row.value,
+            status: SettlementStatus::parse(&row.status)?,
+            attempts: row.attempts as u32,
+            next_attempt_at: row.next_attempt_at as u64,
+            signature: row.signature,
+            error: row.error,
+            created_at: row.created_at as u64,
+            updated_at: row.updated_at as u64,
+        })
+    }
+}
+
+impl SqliteOutbox {
+    pub async fn new(url: &str) -> Result<Self> {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect(url)
+            .await?;
+
+        // a single statement, so servers opening the outbox together settle on the same id
+        let id = crate::utils::id::new();
+        sqlx::query!(
+            "INSERT INTO OutboxIdentity (id) SELECT ? WHERE NOT EXISTS (SELECT 1 FROM OutboxIdentity)",
+            id
+        )
+        .execute(&pool)
+        .await
+        .context("Failed to create outbox identity")?;
+        let id = sqlx::query_scalar!("SELECT id FROM OutboxIdentity LIMIT 1")
+            .fetch_one(&pool)
+            .await
+            .context("Failed to read outbox identity")?;
+
+        Ok(Self { pool, id })
+    }
+}
+
+/// Queues the settlement of `key` in `tx`.
+async fn queue(
+    tx: &mut sqlx::SqliteConnection,
+    integration: &str,
+    key: &str,
+    value: &str,
+) -> Result<i64> {
+    let now = unix_now() as i64;
+    let pending = SettlementStatus::Pending.as_str();
+    let result = sqlx::query!(
+        "INSERT INTO Outbox (integration, key, value, status, attempts, next_attempt_at, created_at, updated_at) VALUES (?, ?, ?, ?, 0, ?, ?, ?)",
+        integration,
+        key,
+        value,
+        pending,
+        now,
+        now,
+        now
+    )
+    .execute(tx)
+    .await
+    .context("Failed to queue settlement")?;
+
+    Ok(result.last_insert_rowid())
+}
+
+#[async_trait]
+impl Outbox for SqliteOutbox {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn store(&self) -> Box<dyn KvBackend> {
+        Box::new(SqliteBackend::from_pool(self.pool.clone()))
+    }
+
+    async fn set(&self, integration: &str, key: &str, value: &str) -> Result<i64> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT OR REPLACE INTO KeyValue (key, value) VALUES (?, ?)",
+            key,
+            value
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to write key")?;
+        let id = queue(&mut tx, integration, key, value).await?;
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        integration: &str,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<Option<i64>> {
+        let mut tx = self.pool.begin().await?;
+        let result = match expected {
+            Some(expected) => {
+                sqlx::query!(
+                    "UPDATE KeyValue SET value = ? WHERE key = ? AND value = ?",
+                    new,
+                    key,
+                    expected
+                )
+                .execute(&mut *tx)
+                .await
+            }
+            None => {
+                sqlx::query!(
+                    "INSERT OR IGNORE INTO KeyValue (key, value) VALUES (?, ?)",
+                    key,
+                    new
+                )
+                .execute(&mut *tx)
+                .await
+            }
+        }
+        .context("Failed to swap key")?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        let id = queue(&mut tx, integration, key, new).await?;
+        tx.commit().await?;
+
+        Ok(Some(id))
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<Settlement>> {
+        let row = sqlx::query_as!(
+            OutboxRow,
+            "SELECT id, integration, key, value, status, attempts, next_attempt_at, signature, error, created_at, updated_at FROM Outbox WHERE id = ?",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Settlement::try_from).transpose()
+    }
+
+    async fn list(&self, key: Option<&str>, limit: u32) -> Result<Vec<Settlement>> {
+        let rows = sqlx::query_as!(
+            OutboxRow,
+            "SELECT id, integration, key, value, status, attempts, next_attempt_at, signature, error, created_at, updated_at FROM Outbox WHERE (? IS NULL OR key = ?) ORDER BY id DESC LIMIT ?",
+            key,
+            key,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(Settlement::try_from).collect()
+    }
+
+    async fn claim(&self, now: u64, lease_until: u64, limit: u32) -> Result<Vec<Settlement>> {
+        let now = now as i64;
+        let lease_until = lease_until as i64;
+        let pending = SettlementStatus::Pending.as_str();
+        let in_flight = SettlementStatus::InFlight.as_str();
+        // a single statement, so two workers never claim the same settlement
+        let rows = sqlx::query_as!(
+            OutboxRow,
+            "UPDATE Outbox SET status = ?, attempts = attempts + 1, next_attempt_at = ?, updated_at = ? WHERE id IN (SELECT id FROM Outbox WHERE status IN (?, ?) AND next_attempt_at <= ? ORDER BY id LIMIT ?) RETURNING id AS \"id!\", integration, key, value, status, attempts, next_attempt_at, signature, error, created_at, updated_at",
+            in_flight,
+            lease_until,
+            now,
+            pending,
+            in_flight,
+            now,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim settlements")?;
+
+        let mut settlements = rows
+            .into_iter()
+            .map(Settlement::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        settlements.sort_by_key(|settlement| settlement.id);
+        Ok(settlements)
+    }
+
+    async fn update(&self, settlement: &Settlement) -> Result<bool> {
+        let status = settlement.status.as_str();
+        let attempts = settlement.attempts as i64;
+        let next_attempt_at = settlement.next_attempt_at as i64;
+        let updated_at = settlement.updated_at as i64;
+        let in_flight = SettlementStatus::InFlight.as_str();
+        let result = sqlx::query!(
+            "UPDATE Outbox SET status = ?, next_attempt_at = ?, signature = ?, error = ?, updated_at = ? WHERE id = ? AND status = ? AND attempts = ?",
+            status,
+            next_attempt_at,
+            settlement.signature,
+            settlement.error,
+            updated_at,
+            settlement.id,
+            in_flight,
+            attempts
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update settlement")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
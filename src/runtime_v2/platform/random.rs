@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Randomness handed to guests, through the `random` interface and `wasi:random`. A seeded
+/// source gives the same bytes whenever an execution is replayed.
+#[derive(Clone)]
+pub enum Random {
+    System,
+    Seeded(Arc<Mutex<ChaCha20Rng>>),
+}
+
+/// Most bytes a guest gets from a single call.
+pub const MAX_BYTES: u64 = 64 * 1024;
+
+impl Random {
+    pub fn seeded(seed: u64) -> Self {
+        Self::Seeded(Arc::new(Mutex::new(ChaCha20Rng::seed_from_u64(seed))))
+    }
+
+    /// `len` random bytes, up to [`MAX_BYTES`].
+    pub fn bytes(&mut self, len: u64) -> Vec<u8> {
+        let mut bytes = vec![0; len.min(MAX_BYTES) as usize];
+        self.fill_bytes(&mut bytes);
+        bytes
+    }
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::System => rand::rngs::OsRng.fill_bytes(dest),
+            Self::Seeded(rng) => rng
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_random() {
+        let mut first = Random::seeded(42);
+        let mut replay = Random::seeded(42);
+        let mut shared = first.clone();
+
+        let a = first.next_u64();
+        assert_eq!(a, replay.next_u64());
+        assert_ne!(shared.next_u64(), a);
+        assert_ne!(Random::seeded(7).next_u64(), a);
+    }
+}
@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::async_trait;
+
+use super::kv::{self, KvBackend};
+use super::outbox::Outbox;
+use super::{local_key, KvError};
+use crate::runtime_v2::integration;
+use crate::runtime_v2::types::{ReadMode, RouteConfig, SinkConfig, WriteMode};
+
+/// Where writes to the keys of a route go besides their store, or where reads of them come from.
+/// Sinks only implement what they support, the routes using them are checked against it when
+/// they are built.
+#[async_trait]
+pub trait Sink: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    async fn read(&self, key: &str) -> anyhow::Result<Option<String>> {
+        anyhow::bail!("{} can't be read from, reading {}", self.name(), key)
+    }
+
+    /// Returns the reference the sink gave the write, like the signature of a transaction.
+    /// `idempotency` is the same on every attempt at a settlement, for the sink to pass on so
+    /// that writes repeated after a lost outcome are dropped.
+    async fn write(
+        &self,
+        key: &str,
+        _value: &str,
+        _idempotency: Option<&str>,
+    ) -> anyhow::Result<Option<String>> {
+        anyhow::bail!("{} can't be written to, writing {}", self.name(), key)
+    }
+}
+
+/// Settlement integrations by the name routes give them, registered when the platform starts.
+/// The outbox worker delivers the writes to the keys of a settlement route to its integration.
+#[derive(Clone, Default)]
+pub struct Integrations {
+    sinks: HashMap<String, Arc<dyn Sink>>,
+}
+
+impl Integrations {
+    /// Integrations the platform comes with.
+    pub fn builtin() -> Self {
+        let mut integrations = Self::default();
+        integrations.register(Arc::new(Solana));
+        integrations
+    }
+
+    /// Registers `sink` under its name, in place of any integration of the same name.
+    pub fn register(&mut self, sink: Arc<dyn Sink>) {
+        self.sinks.insert(sink.name().to_string(), sink);
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<Arc<dyn Sink>> {
+        self.sinks
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Unknown settlement integration: {}", name))
+    }
+}
+
+/// Transfers the value written to a key as solana tokens.
+struct Solana;
+
+#[async_trait]
+impl Sink for Solana {
+    fn name(&self) -> &str {
+        "solana"
+    }
+
+    async fn write(
+        &self,
+        key: &str,
+        value: &str,
+        idempotency: Option<&str>,
+    ) -> anyhow::Result<Option<String>> {
+        tracing::info!(?key, ?value, ?idempotency, "submitting proof");
+        let (key, value) = (local_key(key).to_string(), value.to_string());
+        let idempotency = idempotency.map(str::to_string);
+        // the integration blocks on its request
+        let output = tokio::task::spawn_blocking(move || {
+            integration::solana::transfer_token(key, value, idempotency)
+        })
+        .await??;
+        tracing::info!(signature = ?output, "triggering solana transfer");
+        Ok(Some(output.signature))
+    }
+}
+
+/// Posts writes as `{"key": ..., "value": ...}` to a url, with an `Idempotency-Key` header for
+/// settlements.
+struct Webhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Sink for Webhook {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn write(
+        &self,
+        key: &str,
+        value: &str,
+        idempotency: Option<&str>,
+    ) -> anyhow::Result<Option<String>> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(idempotency) = idempotency {
+            request = request.header("Idempotency-Key", idempotency);
+        }
+        request
+            .body(serde_json::to_vec(
+                &serde_json::json!({ "key": key, "value": value }),
+            )?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(None)
+    }
+}
+
+/// Answers reads from a store kept up to date by someone else.
+struct Mirror {
+    store: Box<dyn KvBackend>,
+}
+
+#[async_trait]
+impl Sink for Mirror {
+    fn name(&self) -> &str {
+        "mirror"
+    }
+
+    async fn read(&self, key: &str) -> anyhow::Result<Option<String>> {
+        self.store.get(key).await
+    }
+}
+
+/// What happens to the keys starting with `prefix`.
+#[derive(Clone)]
+pub struct Route {
+    pub prefix: String,
+    /// Where the values of the keys are kept.
+    pub store: Box<dyn KvBackend>,
+    pub sink: Option<Arc<dyn Sink>>,
+    /// Outbox the writes of settlement routes are queued in, along with their value.
+    pub settle: Option<Settle>,
+    pub read: ReadMode,
+    pub write: WriteMode,
+}
+
+#[derive(Clone)]
+pub struct Settle {
+    pub integration: String,
+    pub outbox: Box<dyn Outbox>,
+}
+
+impl Route {
+    /// Route of the keys no other route takes, kept in `store` alone.
+    pub fn default(store: Box<dyn KvBackend>) -> Self {
+        Self {
+            prefix: String::new(),
+            store,
+            sink: None,
+            settle: None,
+            read: ReadMode::Store,
+            write: WriteMode::Store,
+        }
+    }
+
+    async fn build(
+        default: &dyn KvBackend,
+        outbox: &dyn Outbox,
+        integrations: &Integrations,
+        config: &RouteConfig,
+    ) -> anyhow::Result<Self> {
+        let (store, sink, read, write): (_, Option<Arc<dyn Sink>>, _, _) = match &config.sink {
+            SinkConfig::Kv { store } => (
+                kv::connect(store).await?,
+                None,
+                ReadMode::Store,
+                WriteMode::Store,
+            ),
+            SinkConfig::Settlement { integration } => (
+                outbox.store(),
+                Some(integrations.get(integration)?),
+                ReadMode::Store,
+                WriteMode::Both,
+            ),
+            SinkConfig::Webhook { url, timeout_ms } => (
+                dyn_clone::clone_box(default),
+                Some(Arc::new(Webhook {
+                    url: url.clone(),
+                    client: reqwest::Client::builder()
+                        .timeout(Duration::from_millis(*timeout_ms))
+                        .build()?,
+                })),
+                ReadMode::Store,
+                WriteMode::Both,
+            ),
+            SinkConfig::Mirror { store } => (
+                dyn_clone::clone_box(default),
+                Some(Arc::new(Mirror {
+                    store: kv::connect(store).await?,
+                })),
+                ReadMode::Sink,
+                WriteMode::Deny,
+            ),
+        };
+        let read = config.read.unwrap_or(read);
+        let write = config.write.unwrap_or(write);
+        let settle = match &config.sink {
+            SinkConfig::Settlement { integration } => {
+                anyhow::ensure!(
+                    write != WriteMode::Sink,
+                    "Route {:?} settles values it doesn't store",
+                    config.prefix
+                );
+                Some(Settle {
+                    integration: integration.clone(),
+                    outbox: dyn_clone::clone_box(outbox),
+                })
+            }
+            _ => None,
+        };
+
+        let readable = matches!(config.sink, SinkConfig::Mirror { .. });
+        let writable = matches!(
+            config.sink,
+            SinkConfig::Settlement { .. } | SinkConfig::Webhook { .. }
+        );
+        anyhow::ensure!(
+            read != ReadMode::Sink || readable,
+            "Route {:?} reads from a sink that can't be read",
+            config.prefix
+        );
+        anyhow::ensure!(
+            !matches!(write, WriteMode::Sink | WriteMode::Both) || writable,
+            "Route {:?} writes to a sink that can't be written",
+            config.prefix
+        );
+
+        Ok(Self {
+            prefix: config.prefix.clone(),
+            store,
+            sink,
+            settle,
+            read,
+            write,
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match (self.read, &self.sink) {
+            (ReadMode::Sink, Some(sink)) => sink.read(key).await,
+            _ => self.store.get(key).await,
+        }
+    }
+
+    /// Checks that the keys of the route can be changed in their store, by anything else than
+    /// `set` when `write` is `sink`.
+    fn check_writable(&self, key: &str) -> Result<(), KvError> {
+        match self.write {
+            WriteMode::Store | WriteMode::Both => Ok(()),
+            WriteMode::Sink | WriteMode::Deny => Err(KvError::Denied(format!(
+                "Key {} is routed to a sink it can only be set in",
+                local_key(key)
+            ))),
+        }
+    }
+
+    /// Hands a value that was written to the sink, when the route forwards writes. When the value
+    /// is stored as well the write is already made, so a failure to forward it is only logged.
+    async fn forward(&self, key: &str, value: &str) -> Result<(), KvError> {
+        match (self.write, &self.sink) {
+            (WriteMode::Sink, Some(sink)) => {
+                sink.write(key, value, None).await?;
+            }
+            (WriteMode::Both, Some(sink)) => {
+                if let Err(e) = sink.write(key, value, None).await {
+                    tracing::warn!(
+                        runtime = "platform",
+                        call = "forward",
+                        key,
+                        sink = sink.name(),
+                        error = ?e,
+                        "Failed to forward stored write"
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Settlement the writes to the keys of the route are queued for, when it settles them.
+    fn settlement(&self) -> Option<&Settle> {
+        match self.write {
+            WriteMode::Both => self.settle.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> Result<(), KvError> {
+        if let Some(settle) = self.settlement() {
+            let id = settle.outbox.set(&settle.integration, key, value).await?;
+            tracing::info!(id, key, "Queued settlement");
+            return Ok(());
+        }
+        match self.write {
+            WriteMode::Deny => {
+                return Err(KvError::Denied(format!(
+                    "Key {} is read-only",
+                    local_key(key)
+                )))
+            }
+            WriteMode::Store | WriteMode::Both => self.store.set(key, value).await?,
+            WriteMode::Sink => {}
+        }
+        self.forward(key, value).await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), KvError> {
+        self.check_writable(key)?;
+        // a deletion has nothing to settle, and the settled value would be lost from the store
+        if self.settlement().is_some() {
+            return Err(KvError::Denied(format!(
+                "Key {} is settled and can't be deleted",
+                local_key(key)
+            )));
+        }
+        Ok(self.store.delete(key).await?)
+    }
+
+    pub async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, KvError> {
+        self.check_writable(key)?;
+        if let Some(settle) = self.settlement() {
+            let id = settle
+                .outbox
+                .compare_and_swap(&settle.integration, key, expected, new)
+                .await?;
+            if let Some(id) = id {
+                tracing::info!(id, key, "Queued settlement");
+            }
+            return Ok(id.is_some());
+        }
+        let swapped = self.store.compare_and_swap(key, expected, new).await?;
+        if swapped {
+            self.forward(key, new).await?;
+        }
+        Ok(swapped)
+    }
+
+    pub async fn increment(&self, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.check_writable(key)?;
+        if self.settlement().is_some() {
+            // every value the counter goes through is settled, so it moves by swaps
+            loop {
+                let current = self.store.get(key).await?;
+                let value = kv::add(key, current.as_deref(), delta)?;
+                if self
+                    .compare_and_swap(key, current.as_deref(), &value.to_string())
+                    .await?
+                {
+                    return Ok(value);
+                }
+            }
+        }
+        let value = self.store.increment(key, delta).await?;
+        self.forward(key, &value.to_string()).await?;
+        Ok(value)
+    }
+}
+
+/// Routes of the platform storage, keys are routed on their part after the namespace and go to
+/// the route with the longest prefix they start with.
+#[derive(Clone)]
+pub struct Routes {
+    routes: Vec<Route>,
+    default: Route,
+}
+
+impl Routes {
+    pub fn new(default: Box<dyn KvBackend>) -> Self {
+        Self {
+            routes: Vec::new(),
+            default: Route::default(default),
+        }
+    }
+
+    pub async fn build(
+        default: Box<dyn KvBackend>,
+        outbox: &dyn Outbox,
+        integrations: &Integrations,
+        configs: &[RouteConfig],
+    ) -> anyhow::Result<Self> {
+        let mut routes = Vec::with_capacity(configs.len());
+        for config in configs {
+            anyhow::ensure!(
+                !routes
+                    .iter()
+                    .any(|route: &Route| route.prefix == config.prefix),
+                "Prefix {:?} is routed twice",
+                config.prefix
+            );
+            routes.push(Route::build(default.as_ref(), outbox, integrations, config).await?);
+        }
+        routes.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+
+        Ok(Self {
+            routes,
+            default: Route::default(default),
+        })
+    }
+
+    pub fn route(&self, key: &str) -> &Route {
+        let local = local_key(key);
+        self.routes
+            .iter()
+            .find(|route| local.starts_with(&route.prefix))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_v2::platform::outbox::MemoryOutbox;
+    use crate::runtime_v2::types::KvConfig;
+
+    fn route(prefix: &str, sink: SinkConfig) -> RouteConfig {
+        RouteConfig {
+            prefix: prefix.to_string(),
+            sink,
+            read: None,
+            write: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let default = kv::MemoryBackend::default();
+        let outbox = MemoryOutbox::default();
+        let routes = Routes::build(
+            Box::new(default.clone()),
+            &outbox,
+            &Integrations::builtin(),
+            &[
+                route(
+                    "sol:",
+                    SinkConfig::Settlement {
+                        integration: "solana".to_string(),
+                    },
+                ),
+                route(
+                    "sol:ro:",
+                    SinkConfig::Mirror {
+                        store: KvConfig::Memory,
+                    },
+                ),
+                route(
+                    "fx:",
+                    SinkConfig::Kv {
+                        store: KvConfig::Memory,
+                    },
+                ),
+                route(
+                    "audit:",
+                    SinkConfig::Webhook {
+                        url: "http://127.0.0.1:1/audit".to_string(),
+                        timeout_ms: 100,
+                    },
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(routes.route("ledger/sol:alice").prefix, "sol:");
+        assert_eq!(routes.route("ledger/sol:ro:alice").prefix, "sol:ro:");
+        assert_eq!(routes.route("ledger/alice").prefix, "");
+        // the namespace isn't routed on
+        assert_eq!(routes.route("sol:/alice").prefix, "");
+
+        let mirror = routes.route("ledger/sol:ro:alice");
+        assert!(matches!(
+            mirror.set("ledger/sol:ro:alice", "1").await,
+            Err(KvError::Denied(_))
+        ));
+        assert!(matches!(
+            mirror.increment("ledger/sol:ro:alice", 1).await,
+            Err(KvError::Denied(_))
+        ));
+
+        // kv routes keep their keys apart from the default store
+        let fx = routes.route("ledger/fx:usd");
+        fx.set("ledger/fx:usd", "83").await.unwrap();
+        assert_eq!(
+            fx.get("ledger/fx:usd").await.unwrap().as_deref(),
+            Some("83")
+        );
+        assert_eq!(default.get("ledger/fx:usd").await.unwrap(), None);
+
+        // stored writes stand when the webhook they are forwarded to is down
+        let audit = routes.route("ledger/audit:1");
+        audit.set("ledger/audit:1", "login").await.unwrap();
+        assert_eq!(
+            default.get("ledger/audit:1").await.unwrap().as_deref(),
+            Some("login")
+        );
+
+        // settlements are queued along with the value, not delivered on the spot
+        let sol = routes.route("ledger/sol:alice");
+        sol.set("ledger/sol:alice", "10").await.unwrap();
+        assert_eq!(sol.increment("ledger/sol:alice", 5).await.unwrap(), 15);
+        assert_eq!(
+            outbox
+                .store()
+                .get("ledger/sol:alice")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("15")
+        );
+        let queued = outbox.list(Some("ledger/sol:alice"), 10).await.unwrap();
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].value, "15");
+        assert!(matches!(
+            sol.delete("ledger/sol:alice").await,
+            Err(KvError::Denied(_))
+        ));
+
+        let invalid = Routes::build(
+            Box::new(default),
+            &outbox,
+            &Integrations::builtin(),
+            &[RouteConfig {
+                read: Some(ReadMode::Sink),
+                ..route(
+                    "fx:",
+                    SinkConfig::Kv {
+                        store: KvConfig::Memory,
+                    },
+                )
+            }],
+        )
+        .await;
+        assert!(invalid.is_err());
+    }
+}
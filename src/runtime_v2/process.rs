@@ -12,6 +12,7 @@ pub struct Program {
     pub component: wasmtime::component::Component,
     pub name: String,
     pub version: String,
+    pub allow_bind: bool,
 }
 
 impl ProcessRuntime {
@@ -30,11 +31,13 @@ impl ProcessRuntime {
         name: String,
         version: String,
         component: wasmtime::component::Component,
+        allow_bind: bool,
     ) -> anyhow::Result<String> {
         let program = Program {
             name,
             version,
             component,
+            allow_bind,
         };
         let id = crate::utils::id::new();
         self.programs.insert(&id, program).await?;
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::driver::DriverInfo;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Calls made to a driver since the start of its current window.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    calls: u32,
+}
+
+/// Call-rate limit over one minute windows, per driver unless keyed otherwise, shared by every
+/// clone of the driver runtime. A limit of `0` disables it.
+#[derive(Clone)]
+pub struct RateLimiter<K = DriverInfo> {
+    calls_per_minute: u32,
+    windows: Arc<Mutex<HashMap<K, Window>>>,
+}
+
+impl<K: Clone + Eq + Hash> RateLimiter<K> {
+    pub fn new(calls_per_minute: u32) -> Self {
+        Self {
+            calls_per_minute,
+            windows: Arc::default(),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.calls_per_minute
+    }
+
+    /// Counts a call against `key`. Once it used up its calls, returns how long until the window
+    /// resets.
+    pub fn acquire(&self, key: &K) -> Result<(), Duration> {
+        self.acquire_at(key, Instant::now())
+    }
+
+    fn acquire_at(&self, key: &K, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let window = windows.entry(key.clone()).or_insert(Window {
+            started: now,
+            calls: 0,
+        });
+
+        if now.duration_since(window.started) >= WINDOW {
+            *window = Window {
+                started: now,
+                calls: 0,
+            };
+        }
+
+        if self.calls_per_minute != 0 && window.calls >= self.calls_per_minute {
+            return Err(WINDOW - now.duration_since(window.started));
+        }
+
+        window.calls += 1;
+        Ok(())
+    }
+
+    /// Calls made by `key` in the current window.
+    pub fn usage(&self, key: &K) -> u32 {
+        let windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows
+            .get(key)
+            .filter(|window| window.started.elapsed() < WINDOW)
+            .map(|window| window.calls)
+            .unwrap_or(0)
+    }
+
+    pub fn forget(&self, key: &K) {
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let driver_info = DriverInfo {
+            name: "driver".to_string(),
+            version: "0.1.0".to_string(),
+        };
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at(&driver_info, now).is_ok());
+        assert!(limiter.acquire_at(&driver_info, now).is_ok());
+        assert_eq!(
+            limiter.acquire_at(&driver_info, now + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert!(limiter.acquire_at(&driver_info, now + WINDOW).is_ok());
+        assert!(RateLimiter::new(0).acquire_at(&driver_info, now).is_ok());
+    }
+}
@@ -0,0 +1,354 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::ensure;
+
+/// What a driver is allowed to reach outside of itself, built from the capabilities in its
+/// manifest and the grants in the admin configuration. The default allows nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    pub http: HttpAllowlist,
+    pub http_limits: HttpLimits,
+    pub storage: StorageScope,
+}
+
+/// Bounds of every http request a driver sends.
+#[derive(Debug, Clone)]
+pub struct HttpLimits {
+    pub timeout: Duration,
+    pub max_response_size: usize,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_response_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Prefix of the keys in a shared namespace, `shared:{namespace}/{key}`.
+pub const SHARED_PREFIX: &str = "shared:";
+
+/// Where the storage keys of a driver live. Keys are stored as `{namespace}/{key}`, keys in a
+/// namespace the admin shared with the driver are used as they are. A driver whose manifest
+/// declares storage prefixes can only use keys of its own starting with one of them.
+#[derive(Debug, Clone, Default)]
+pub struct StorageScope {
+    namespace: String,
+    shared: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl StorageScope {
+    pub fn new(namespace: String, shared: Vec<String>, prefixes: Vec<String>) -> Self {
+        Self {
+            namespace,
+            shared,
+            prefixes,
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Key in the platform storage for `key` of the driver.
+    pub fn resolve(&self, key: &str) -> Result<String, ScopeError> {
+        if key.is_empty() {
+            return Err(ScopeError::InvalidKey("Key can't be empty".to_string()));
+        }
+
+        if let Some(rest) = key.strip_prefix(SHARED_PREFIX) {
+            let (namespace, local) = rest.split_once('/').ok_or_else(|| {
+                ScopeError::InvalidKey(format!(
+                    "Shared keys are written as {}{{namespace}}/{{key}}",
+                    SHARED_PREFIX
+                ))
+            })?;
+            if local.is_empty() {
+                return Err(ScopeError::InvalidKey("Key can't be empty".to_string()));
+            }
+            if !self.shared.iter().any(|shared| shared == namespace) {
+                return Err(ScopeError::PermissionDenied(format!(
+                    "Namespace {} is not shared with the driver",
+                    namespace
+                )));
+            }
+            return Ok(key.to_string());
+        }
+
+        self.resolve_own("Key", key)
+    }
+
+    /// Prefix in the platform storage of the keys of the driver starting with `prefix`. Unlike
+    /// keys, prefixes can be empty, and like them they have to start with one of the storage
+    /// prefixes of the driver when it declares some.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<String, ScopeError> {
+        if let Some(rest) = prefix.strip_prefix(SHARED_PREFIX) {
+            let (namespace, _) = rest.split_once('/').ok_or_else(|| {
+                ScopeError::InvalidKey(format!(
+                    "Shared prefixes are written as {}{{namespace}}/{{prefix}}",
+                    SHARED_PREFIX
+                ))
+            })?;
+            if !self.shared.iter().any(|shared| shared == namespace) {
+                return Err(ScopeError::PermissionDenied(format!(
+                    "Namespace {} is not shared with the driver",
+                    namespace
+                )));
+            }
+            return Ok(prefix.to_string());
+        }
+
+        self.resolve_own("Prefix", prefix)
+    }
+
+    /// `key` in the namespace of the driver, within its storage prefixes.
+    fn resolve_own(&self, what: &str, key: &str) -> Result<String, ScopeError> {
+        if self.namespace.is_empty() {
+            return Err(ScopeError::PermissionDenied(
+                "Driver has no storage namespace".to_string(),
+            ));
+        }
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|p| key.starts_with(p)) {
+            return Err(ScopeError::PermissionDenied(format!(
+                "{} {} is outside of the storage prefixes of the driver",
+                what, key
+            )));
+        }
+
+        Ok(format!("{}/{}", self.namespace, key))
+    }
+
+    /// Key of the driver for `key` in the platform storage, the reverse of [`Self::resolve`].
+    pub fn local<'a>(&self, key: &'a str) -> &'a str {
+        if key.starts_with(SHARED_PREFIX) {
+            return key;
+        }
+        key.strip_prefix(&self.namespace)
+            .and_then(|key| key.strip_prefix('/'))
+            .unwrap_or(key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeError {
+    InvalidKey(String),
+    PermissionDenied(String),
+}
+
+/// Endpoints a driver can send http requests to.
+#[derive(Debug, Clone, Default)]
+pub struct HttpAllowlist {
+    rules: Vec<HttpRule>,
+}
+
+/// A single allowlist entry, written as `[scheme://]host[:port]`. The scheme defaults to
+/// `https` and the port to the scheme's, a host starting with `*.` matches its subdomains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRule {
+    scheme: String,
+    host: String,
+    port: u16,
+}
+
+impl FromStr for HttpRule {
+    type Err = anyhow::Error;
+
+    fn from_str(entry: &str) -> anyhow::Result<Self> {
+        let (scheme, rest) = match entry.split_once("://") {
+            Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+            None => ("https".to_string(), entry),
+        };
+        ensure!(
+            scheme == "http" || scheme == "https",
+            "Unsupported scheme in {:?}",
+            entry
+        );
+        ensure!(!rest.contains('/'), "Unexpected path in {:?}", entry);
+
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| anyhow::anyhow!("Invalid port in {:?}", entry))?,
+            ),
+            _ => (rest, if scheme == "http" { 80 } else { 443 }),
+        };
+        let wildcard = host.strip_prefix("*.").unwrap_or(host);
+        ensure!(
+            !wildcard.is_empty() && !wildcard.contains('*'),
+            "Invalid host in {:?}",
+            entry
+        );
+
+        Ok(Self {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl HttpRule {
+    fn matches(&self, url: &reqwest::Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+
+        let host_matches = match self.host.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            None => host == self.host,
+        };
+
+        host_matches
+            && url.scheme() == self.scheme
+            && url.port_or_known_default() == Some(self.port)
+    }
+}
+
+impl HttpAllowlist {
+    pub fn parse<'a>(entries: impl IntoIterator<Item = &'a String>) -> anyhow::Result<Self> {
+        let rules = entries
+            .into_iter()
+            .map(|entry| entry.parse())
+            .collect::<anyhow::Result<Vec<HttpRule>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn allows(&self, url: &reqwest::Url) -> bool {
+        self.rules.iter().any(|rule| rule.matches(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(entries: &[&str]) -> HttpAllowlist {
+        let entries = entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        HttpAllowlist::parse(&entries).unwrap()
+    }
+
+    fn url(url: &str) -> reqwest::Url {
+        reqwest::Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_http_allowlist() {
+        let list = allowlist(&["api.example.com", "http://localhost:8080", "*.bank.in"]);
+
+        assert!(list.allows(&url("https://api.example.com/v1/pay")));
+        assert!(list.allows(&url("https://API.example.com:443/")));
+        assert!(!list.allows(&url("http://api.example.com/")));
+        assert!(!list.allows(&url("https://api.example.com:8443/")));
+        assert!(!list.allows(&url("https://api.example.com.evil.io/")));
+
+        assert!(list.allows(&url("http://localhost:8080/callback")));
+        assert!(!list.allows(&url("http://localhost/")));
+
+        assert!(list.allows(&url("https://upi.bank.in/")));
+        assert!(!list.allows(&url("https://bank.in/")));
+        assert!(!list.allows(&url("https://evilbank.in/")));
+
+        assert!(!HttpAllowlist::default().allows(&url("https://api.example.com/")));
+
+        assert!("ftp://example.com".parse::<HttpRule>().is_err());
+        assert!("example.com/path".parse::<HttpRule>().is_err());
+        assert!("example.com:port".parse::<HttpRule>().is_err());
+        assert!("*".parse::<HttpRule>().is_err());
+    }
+
+    #[test]
+    fn test_storage_scope() {
+        let scope = StorageScope::new("ledger@0.1.0".to_string(), vec!["fx".to_string()], vec![]);
+
+        assert_eq!(scope.resolve("alice"), Ok("ledger@0.1.0/alice".to_string()));
+        assert_eq!(
+            scope.resolve("sol:alice"),
+            Ok("ledger@0.1.0/sol:alice".to_string())
+        );
+        assert_eq!(
+            scope.resolve("shared:fx/rates"),
+            Ok("shared:fx/rates".to_string())
+        );
+        assert!(matches!(
+            scope.resolve("shared:other/rates"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            scope.resolve("shared:fx"),
+            Err(ScopeError::InvalidKey(_))
+        ));
+        assert!(matches!(scope.resolve(""), Err(ScopeError::InvalidKey(_))));
+        assert!(matches!(
+            StorageScope::default().resolve("alice"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+
+        assert_eq!(scope.resolve_prefix(""), Ok("ledger@0.1.0/".to_string()));
+        assert_eq!(
+            scope.resolve_prefix("shared:fx/"),
+            Ok("shared:fx/".to_string())
+        );
+        assert!(matches!(
+            scope.resolve_prefix("shared:other/"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+        assert_eq!(scope.local("ledger@0.1.0/sol:alice"), "sol:alice");
+        assert_eq!(scope.local("shared:fx/rates"), "shared:fx/rates");
+
+        let scope = StorageScope::new(
+            "ledger".to_string(),
+            vec!["fx".to_string()],
+            vec!["txn:".to_string()],
+        );
+        assert_eq!(scope.resolve("txn:1"), Ok("ledger/txn:1".to_string()));
+        assert!(matches!(
+            scope.resolve("alice"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+        assert_eq!(
+            scope.resolve("shared:fx/rates"),
+            Ok("shared:fx/rates".to_string())
+        );
+    }
+
+    #[test]
+    fn test_storage_scope_prefixes() {
+        let scope = StorageScope::new(
+            "ledger".to_string(),
+            vec!["fx".to_string()],
+            vec!["txn:".to_string()],
+        );
+
+        assert_eq!(scope.resolve_prefix("txn:"), Ok("ledger/txn:".to_string()));
+        assert_eq!(
+            scope.resolve_prefix("txn:1"),
+            Ok("ledger/txn:1".to_string())
+        );
+        // listing everything would reach keys outside of the declared prefixes
+        assert!(matches!(
+            scope.resolve_prefix(""),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            scope.resolve_prefix("alice"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+        assert_eq!(
+            scope.resolve_prefix("shared:fx/"),
+            Ok("shared:fx/".to_string())
+        );
+        assert!(matches!(
+            scope.resolve_prefix("shared:other/"),
+            Err(ScopeError::PermissionDenied(_))
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::ensure;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Context of the key derivation, changing it makes every stored secret unreadable.
+const KEY_CONTEXT: &str = "units 2025-02-25 driver secrets";
+
+const NONCE_SIZE: usize = 12;
+
+/// Key secrets are encrypted with at rest, derived from key material the admin provides through
+/// the environment. Sealed secrets are stored as `nonce || ciphertext`.
+#[derive(Clone)]
+pub struct SecretKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SecretKey {
+    pub fn derive(material: &[u8]) -> Self {
+        let key = blake3::derive_key(KEY_CONTEXT, material);
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// Key derived from the environment variable `var`, if it is set.
+    pub fn from_env(var: &str) -> Option<Self> {
+        std::env::var(var)
+            .ok()
+            .filter(|material| !material.is_empty())
+            .map(|material| Self::derive(material.as_bytes()))
+    }
+
+    /// Encrypts `plaintext`, bound to `aad` so it can't be moved to another record.
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        ensure!(sealed.len() > NONCE_SIZE, "Sealed secret is too short");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| {
+                anyhow::anyhow!("Failed to decrypt secret, it was sealed with another key")
+            })
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let key = SecretKey::derive(b"material");
+        let sealed = key.seal(b"upi/api_key", b"hunter2").unwrap();
+
+        assert_ne!(&sealed[NONCE_SIZE..], b"hunter2");
+        assert_eq!(key.open(b"upi/api_key", &sealed).unwrap(), b"hunter2");
+        assert!(key.open(b"upi/other", &sealed).is_err());
+        assert!(SecretKey::derive(b"other")
+            .open(b"upi/api_key", &sealed)
+            .is_err());
+        assert!(key.open(b"upi/api_key", &sealed[..NONCE_SIZE]).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use super::Runtime;
+use crate::error::Error as UnitsError;
 use crate::runtime_v2::driver::DriverInfo;
+use crate::runtime_v2::manifest::DriverManifest;
 use crate::runtime_v2::types::ProcessState;
 use crate::runtime_v2::types::UserCtx;
 use crate::service::proto_types::DriverDetail;
@@ -16,6 +18,7 @@ mod server_traits {
         driver_details_server::DriverDetails, // for driver details
         driver_server::Driver,
         execution_server::Execution,
+        settlement_server::Settlement,
         user_check_server::UserCheck,
         user_login_server::UserLogin,
         user_sign_up_server::UserSignUp,
@@ -36,20 +39,45 @@ struct UserData {
 }
 
 mod types {
+    pub use crate::service::proto_types::SettlementEntry;
     pub use crate::service::proto_types::{BindRequest, BindResponse};
     pub use crate::service::proto_types::{CheckRequest, CheckResponse};
+    pub use crate::service::proto_types::{
+        DriverConfigEntry, GetDriverConfigRequest, GetDriverConfigResponse,
+    };
     pub use crate::service::proto_types::{DriverDetailsRequest, DriverDetailsResponse};
+    pub use crate::service::proto_types::{DriverKey, KeySignature};
     pub use crate::service::proto_types::{ExecutionRequest, ExecutionResponse};
+    pub use crate::service::proto_types::{GetSettlementRequest, GetSettlementResponse};
+    pub use crate::service::proto_types::{ListDriverKeysRequest, ListDriverKeysResponse};
+    pub use crate::service::proto_types::{ListKeySignaturesRequest, ListKeySignaturesResponse};
     pub use crate::service::proto_types::{ListProgramRequest, ListProgramResponse, Program};
     pub use crate::service::proto_types::{ListResolverRequest, ListResolverResponse, PathMapping};
+    pub use crate::service::proto_types::{ListSettlementsRequest, ListSettlementsResponse};
     pub use crate::service::proto_types::{LoadDriverRequest, LoadDriverResponse};
     pub use crate::service::proto_types::{LoginRequest, LoginResponse};
+    pub use crate::service::proto_types::{ProvisionDriverKeyRequest, ProvisionDriverKeyResponse};
+    pub use crate::service::proto_types::{RotateDriverKeyRequest, RotateDriverKeyResponse};
+    pub use crate::service::proto_types::{SetDriverConfigRequest, SetDriverConfigResponse};
     pub use crate::service::proto_types::{SignUpRequest, SignUpResponse};
     pub use crate::service::proto_types::{SubmitProgramRequest, SubmitProgramResponse};
     pub use crate::service::proto_types::{UnbindRequest, UnbindResponse};
     pub use crate::service::proto_types::{UnloadDriverRequest, UnloadDriverResponse};
 }
 
+/// Metadata admin calls carry the admin token in.
+const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+fn check_admin<T>(runtime: &Runtime, request: &Request<T>) -> Result<(), UnitsError> {
+    let token = request
+        .metadata()
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .map(str::trim);
+
+    runtime.driver_layer.check_admin(token)
+}
+
 fn check_jwt<T>(request: &Request<T>) -> Result<UserData, Box<dyn Error>> {
     let token = match request.metadata().get("Authorization") {
         Some(token) => token
@@ -133,29 +161,33 @@ impl server_traits::Execution for super::Runtime {
         &self,
         request: Request<types::ExecutionRequest>,
     ) -> Result<Response<types::ExecutionResponse>, tonic::Status> {
-        let user_id = get_user_id(&request).map_err(|e| tonic::Status::internal(e.to_string()))?;
+        let user_id =
+            get_user_id(&request).map_err(|e| UnitsError::Unauthenticated(e.to_string()))?;
         let request = request.into_inner();
         let output = execte(self.clone(), request, user_id)
             .await
             .inspect_err(|err| {
                 tracing::error!(error = ?err, "Execution failed");
-            })
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            })?;
         Ok(Response::new(output))
     }
     async fn submit(
         &self,
         request: Request<types::SubmitProgramRequest>,
     ) -> Result<Response<types::SubmitProgramResponse>, tonic::Status> {
+        // programs allowed to bind rebind paths in the home of whoever runs them
+        if request.get_ref().allow_bind {
+            check_admin(self, &request)?;
+        }
         let request = request.into_inner();
         let component =
             wasmtime::component::Component::new(&self.process_layer.engine, request.binary)
-                .map_err(|e| tonic::Status::internal(e.to_string()))?;
+                .map_err(|e| UnitsError::invalid_argument("binary", e))?;
         let id = self
             .process_layer
-            .store_program(request.name, request.version, component)
+            .store_program(request.name, request.version, component, request.allow_bind)
             .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            .map_err(UnitsError::Internal)?;
 
         Ok(Response::new(types::SubmitProgramResponse {
             program_id: id,
@@ -172,12 +204,13 @@ impl server_traits::Execution for super::Runtime {
                 .programs
                 .list(self.process_layer.engine.clone())
                 .await
-                .map_err(|e| tonic::Status::internal(e.to_string()))?
+                .map_err(UnitsError::Internal)?
                 .into_iter()
                 .map(|(id, program)| types::Program {
                     program_id: id.clone(),
                     name: program.name.clone(),
                     version: program.version.clone(),
+                    allow_bind: program.allow_bind,
                 })
                 .collect(),
         }))
@@ -188,29 +221,41 @@ async fn execte(
     runtime: Runtime,
     request: types::ExecutionRequest,
     user_id: String,
-) -> anyhow::Result<types::ExecutionResponse> {
-    let component = match (request.program_id, request.binary) {
+) -> crate::Result<types::ExecutionResponse> {
+    // ad-hoc binaries never get more than the default policy
+    let (component, policy) = match (request.program_id, request.binary) {
         (Some(program_id), None) => runtime
             .process_layer
             .find_program(&program_id, runtime.process_layer.engine.clone())
             .await?
-            .map(|prog| prog.component)
-            .ok_or_else(|| anyhow::anyhow!("Program not found"))?,
-        (None, Some(binary)) => {
-            wasmtime::component::Component::new(&runtime.process_layer.engine, binary)?
-        }
+            .map(|prog| {
+                (
+                    prog.component,
+                    super::types::ProgramPolicy {
+                        allow_bind: prog.allow_bind,
+                    },
+                )
+            })
+            .ok_or_else(|| UnitsError::not_found("program", program_id))?,
+        (None, Some(binary)) => (
+            wasmtime::component::Component::new(&runtime.process_layer.engine, binary)
+                .map_err(|e| UnitsError::invalid_argument("binary", e))?,
+            super::types::ProgramPolicy::default(),
+        ),
         _ => {
-            anyhow::bail!("Either program_id or binary should be provided (but not both)")
+            return Err(UnitsError::invalid_argument(
+                "program_id",
+                "Either program_id or binary should be provided (but not both)",
+            ))
         }
     };
 
     let output = runtime
         .exec(
-            super::types::UserCtx {
-                user_id: user_id.to_string(),
-            },
+            super::types::UserCtx::new(user_id.to_string()),
             component,
             request.input,
+            policy,
         )
         .await?;
 
@@ -223,24 +268,19 @@ impl server_traits::Bind for super::Runtime {
         &self,
         request: Request<types::BindRequest>,
     ) -> Result<Response<types::BindResponse>, tonic::Status> {
-        let user_id = get_user_id(&request).map_err(|e| tonic::Status::internal(e.to_string()))?;
+        let user_id =
+            get_user_id(&request).map_err(|e| UnitsError::Unauthenticated(e.to_string()))?;
         let request = request.into_inner();
         let mut process_state = ProcessState::new(
-            UserCtx {
-                user_id: user_id.to_string(),
-            },
+            UserCtx::new(user_id.to_string()),
             self.driver_layer.clone(),
             self.platform_layer.clone(),
             self.event_sender.clone(),
         );
 
-        let path = if let Some(suffix) = request.path.strip_prefix("~/") {
-            format!("/accounts/{}/{}", user_id, suffix)
-        } else {
-            request.path.clone()
-        };
+        let path = process_state.ctx.expand_path(&request.path);
 
-        process_state
+        if let Err(e) = process_state
             .perform_bind(
                 path,
                 DriverInfo {
@@ -250,7 +290,13 @@ impl server_traits::Bind for super::Runtime {
                 request.account_info.clone(),
             )
             .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+        {
+            return Err(match process_state.failure_of(&e) {
+                Some(failure) => UnitsError::from(failure),
+                None => UnitsError::from(e),
+            }
+            .into());
+        }
 
         let output = types::BindResponse {
             driver_name: request.driver_name,
@@ -270,7 +316,7 @@ impl server_traits::Bind for super::Runtime {
         let output = self.driver_layer.resolver.remove(&request.path).await;
 
         match output {
-            None => Err(tonic::Status::not_found("Path not found")),
+            None => Err(UnitsError::not_found("path", request.path).into()),
             Some(path_info) => Ok(Response::new(types::UnbindResponse {
                 driver_name: path_info.driver_name,
                 driver_version: path_info.driver_version,
@@ -315,9 +361,13 @@ impl server_traits::Driver for super::Runtime {
 
         tracing::info!(name = %request.driver_name,version=%request.driver_version, "Adding driver");
 
+        self.driver_layer
+            .check_component_size(request.driver_binary.len())?;
+        let manifest = request.manifest.map(DriverManifest::try_from).transpose()?;
+
         let module =
             wasmtime::component::Component::new(&self.driver_layer.engine, request.driver_binary)
-                .map_err(|e| tonic::Status::internal(e.to_string()))?;
+                .map_err(|e| UnitsError::invalid_argument("driver_binary", e))?;
 
         tracing::info!(name = ?request.driver_name, "Module Created");
 
@@ -326,9 +376,9 @@ impl server_traits::Driver for super::Runtime {
                 request.driver_name.clone(),
                 module,
                 request.driver_version.clone(),
+                manifest,
             )
-            .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            .await?;
 
         Ok(tonic::Response::new(types::LoadDriverResponse {
             driver_name: request.driver_name,
@@ -350,13 +400,228 @@ impl server_traits::Driver for super::Runtime {
         self.driver_layer
             .remove_driver(driver_info)
             .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            .map_err(UnitsError::Internal)?;
 
         Ok(tonic::Response::new(types::UnloadDriverResponse {
             driver_name: request.driver_name,
             driver_version: request.driver_version,
         }))
     }
+
+    async fn set_driver_config(
+        &self,
+        request: Request<types::SetDriverConfigRequest>,
+    ) -> Result<Response<types::SetDriverConfigResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+
+        self.driver_layer
+            .set_config(
+                &request.driver_name,
+                &request.key,
+                request.value,
+                request.secret,
+            )
+            .await?;
+
+        Ok(tonic::Response::new(types::SetDriverConfigResponse {
+            driver_name: request.driver_name,
+            key: request.key,
+        }))
+    }
+
+    async fn get_driver_config(
+        &self,
+        request: Request<types::GetDriverConfigRequest>,
+    ) -> Result<Response<types::GetDriverConfigResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+
+        let entries = self
+            .driver_layer
+            .redacted_config(&request.driver_name)
+            .await?
+            .into_iter()
+            .map(|(key, value, secret)| types::DriverConfigEntry { key, value, secret })
+            .collect();
+
+        Ok(tonic::Response::new(types::GetDriverConfigResponse {
+            driver_name: request.driver_name,
+            entries,
+        }))
+    }
+
+    async fn provision_driver_key(
+        &self,
+        request: Request<types::ProvisionDriverKeyRequest>,
+    ) -> Result<Response<types::ProvisionDriverKeyResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+
+        let key = self
+            .driver_layer
+            .provision_key(&request.driver_name, &request.key_name, &request.algorithm)
+            .await?;
+
+        Ok(tonic::Response::new(types::ProvisionDriverKeyResponse {
+            driver_name: request.driver_name,
+            key: Some(driver_key(key)),
+        }))
+    }
+
+    async fn rotate_driver_key(
+        &self,
+        request: Request<types::RotateDriverKeyRequest>,
+    ) -> Result<Response<types::RotateDriverKeyResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+
+        let key = self
+            .driver_layer
+            .rotate_key(&request.driver_name, &request.key_name)
+            .await?;
+
+        Ok(tonic::Response::new(types::RotateDriverKeyResponse {
+            driver_name: request.driver_name,
+            key: Some(driver_key(key)),
+        }))
+    }
+
+    async fn list_driver_keys(
+        &self,
+        request: Request<types::ListDriverKeysRequest>,
+    ) -> Result<Response<types::ListDriverKeysResponse>, tonic::Status> {
+        let request = request.into_inner();
+
+        let keys = self
+            .driver_layer
+            .list_keys(&request.driver_name)
+            .await?
+            .into_iter()
+            .map(driver_key)
+            .collect();
+
+        Ok(tonic::Response::new(types::ListDriverKeysResponse {
+            driver_name: request.driver_name,
+            keys,
+        }))
+    }
+
+    async fn list_key_signatures(
+        &self,
+        request: Request<types::ListKeySignaturesRequest>,
+    ) -> Result<Response<types::ListKeySignaturesResponse>, tonic::Status> {
+        // admins see every signature of the driver, users only the ones made for them
+        let user_id = match check_admin(self, &request) {
+            Ok(()) => None,
+            Err(_) => Some(
+                get_user_id(&request).map_err(|e| UnitsError::Unauthenticated(e.to_string()))?,
+            ),
+        };
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => 100,
+            limit => limit,
+        };
+
+        let signatures = self
+            .driver_layer
+            .key_signatures(&request.driver_name, user_id.as_deref(), limit)
+            .await?
+            .into_iter()
+            .map(|record| types::KeySignature {
+                driver_version: record.driver_version,
+                key_name: record.key_name,
+                key_version: record.key_version,
+                user_id: record.user_id,
+                execution_id: record.execution_id,
+                payload_hash: record.payload_hash,
+                signed_at: record.signed_at,
+            })
+            .collect();
+
+        Ok(tonic::Response::new(types::ListKeySignaturesResponse {
+            driver_name: request.driver_name,
+            signatures,
+        }))
+    }
+}
+
+fn driver_key(key: crate::runtime_v2::storage::KeyRecord) -> types::DriverKey {
+    types::DriverKey {
+        key_name: key.key_name,
+        version: key.version,
+        algorithm: key.algorithm.as_str().to_string(),
+        public_key: key.public_key,
+        created_at: key.created_at,
+    }
+}
+
+#[tonic::async_trait]
+impl server_traits::Settlement for super::Runtime {
+    async fn get_settlement(
+        &self,
+        request: Request<types::GetSettlementRequest>,
+    ) -> Result<Response<types::GetSettlementResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+
+        let settlement = self
+            .platform_layer
+            .outbox
+            .get(request.id)
+            .await
+            .map_err(UnitsError::Internal)?
+            .ok_or_else(|| UnitsError::not_found("settlement", request.id.to_string()))?;
+
+        Ok(tonic::Response::new(types::GetSettlementResponse {
+            settlement: Some(settlement_entry(settlement)),
+        }))
+    }
+
+    async fn list_settlements(
+        &self,
+        request: Request<types::ListSettlementsRequest>,
+    ) -> Result<Response<types::ListSettlementsResponse>, tonic::Status> {
+        check_admin(self, &request)?;
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => 100,
+            limit => limit,
+        };
+
+        let settlements = self
+            .platform_layer
+            .outbox
+            .list(request.key.as_deref(), limit)
+            .await
+            .map_err(UnitsError::Internal)?
+            .into_iter()
+            .map(settlement_entry)
+            .collect();
+
+        Ok(tonic::Response::new(types::ListSettlementsResponse {
+            settlements,
+        }))
+    }
+}
+
+fn settlement_entry(
+    settlement: crate::runtime_v2::platform::outbox::Settlement,
+) -> types::SettlementEntry {
+    types::SettlementEntry {
+        id: settlement.id,
+        integration: settlement.integration,
+        key: settlement.key,
+        value: settlement.value,
+        status: settlement.status.as_str().to_string(),
+        attempts: settlement.attempts,
+        next_attempt_at: settlement.next_attempt_at,
+        signature: settlement.signature,
+        error: settlement.error,
+        created_at: settlement.created_at,
+        updated_at: settlement.updated_at,
+    }
 }
 
 #[tonic::async_trait]
@@ -367,15 +632,27 @@ impl server_traits::DriverDetails for super::Runtime {
     ) -> Result<Response<types::DriverDetailsResponse>, tonic::Status> {
         let mut all_driver_details = Vec::<DriverDetail>::new();
         let mut message = String::from("Drivers Detail list found!!");
-        let reader = &self.driver_layer.drivers;
-        for (driver_info, _module) in reader
-            .list(self.driver_layer.engine.clone())
+        let config = &self.driver_layer.config;
+        let rate_limiter = &self.driver_layer.rate_limiter;
+        for driver_info in self
+            .driver_layer
+            .drivers
+            .list_info()
             .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?
+            .map_err(UnitsError::Internal)?
         {
+            let manifest = self
+                .driver_layer
+                .drivers
+                .manifest(&driver_info)
+                .await
+                .map_err(UnitsError::Internal)?;
             let new_driver = DriverDetail {
                 name: driver_info.name.clone(),
                 version: driver_info.version.clone(),
+                calls_last_minute: rate_limiter.usage(&driver_info),
+                calls_per_minute: rate_limiter.limit(),
+                manifest: manifest.map(Into::into),
             };
             all_driver_details.push(new_driver);
         }
@@ -386,7 +663,11 @@ impl server_traits::DriverDetails for super::Runtime {
 
         Ok(tonic::Response::new(types::DriverDetailsResponse {
             message,
+            driver_count: all_driver_details.len() as u32,
             driver_data: all_driver_details,
+            driver_limit: config.driver_limit,
+            max_versions: config.max_versions,
+            max_component_size: config.max_component_size as u64,
         }))
     }
 }
@@ -406,7 +687,7 @@ impl server_traits::UserSignUp for super::Runtime {
             .user
             .insert(&request.user_name, &hash_pass.to_string())
             .await
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            .map_err(UnitsError::Internal)?;
         let message = format!("{} has signed up successfully", request.user_name);
         Ok(tonic::Response::new(types::SignUpResponse { message }))
     }
@@ -482,7 +763,8 @@ impl server_traits::UserCheck for super::Runtime {
         &self,
         request: Request<types::CheckRequest>,
     ) -> Result<Response<types::CheckResponse>, tonic::Status> {
-        let user_data = check_jwt(&request).map_err(|e| tonic::Status::internal(e.to_string()))?;
+        let user_data =
+            check_jwt(&request).map_err(|e| UnitsError::Unauthenticated(e.to_string()))?;
         match user_data.user_name {
             Some(user_name) => Ok(tonic::Response::new(types::CheckResponse {
                 message: user_data.message,
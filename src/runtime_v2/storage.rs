@@ -5,7 +5,10 @@ use std::{
 
 use tonic::async_trait;
 
-use super::{driver::DriverInfo, process::Program, resolver::PathInfo};
+use super::{
+    coordinator::TransactionRecord, driver::DriverInfo, keystore::KeyAlgorithm,
+    manifest::DriverManifest, process::Program, resolver::PathInfo,
+};
 
 #[derive(Eq, Hash, PartialEq)]
 pub struct UserInfo {
@@ -18,7 +21,53 @@ pub struct PersistentStorage {
     mount_points: Arc<RwLock<HashMap<String, PathInfo>>>,
     pub programs: Arc<RwLock<HashMap<String, Program>>>,
     pub drivers: Arc<RwLock<HashMap<DriverInfo, wasmtime::component::Component>>>,
+    pub manifests: Arc<RwLock<HashMap<DriverInfo, DriverManifest>>>,
     pub user: Arc<RwLock<HashMap<UserInfo, String>>>,
+    pub transactions: Arc<RwLock<HashMap<String, TransactionRecord>>>,
+    pub driver_config: Arc<RwLock<HashMap<String, HashMap<String, ConfigEntry>>>>,
+    pub keys: Arc<RwLock<Vec<KeyRecord>>>,
+    pub signatures: Arc<RwLock<Vec<SignatureRecord>>>,
+}
+
+/// How many drivers, and versions of a single driver, can be loaded at once. Replacing a
+/// version that is already loaded doesn't count against them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DriverLimits {
+    pub drivers: u32,
+    pub versions: u32,
+}
+
+/// A configuration value of a driver as it is stored, sealed when it is a secret.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub value: Vec<u8>,
+    pub secret: bool,
+}
+
+/// A version of a key provisioned for a driver, its private key sealed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRecord {
+    pub driver_name: String,
+    pub key_name: String,
+    pub version: u32,
+    pub algorithm: KeyAlgorithm,
+    pub public_key: Vec<u8>,
+    pub private_key: Vec<u8>,
+    pub created_at: u64,
+}
+
+/// Audit entry of a signature a driver made with one of its keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureRecord {
+    pub driver_name: String,
+    pub driver_version: String,
+    pub key_name: String,
+    pub key_version: u32,
+    pub user_id: String,
+    pub execution_id: String,
+    /// Hex encoded sha256 of the signed payload.
+    pub payload_hash: String,
+    pub signed_at: u64,
 }
 
 mod private {
@@ -30,6 +79,7 @@ mod private {
 pub trait Resolver: dyn_clone::DynClone + private::Safety {
     async fn remove(&self, path: &str) -> Option<PathInfo>;
     async fn list(&self) -> Vec<(String, PathInfo)>;
+    async fn list_prefix(&self, prefix: &str) -> Vec<(String, PathInfo)>;
     async fn get(&self, path: &str) -> Option<PathInfo>;
     async fn insert(&self, path: String, path_info: PathInfo) -> Option<()>;
 }
@@ -48,16 +98,23 @@ pub trait ProgramStorage: dyn_clone::DynClone + private::Safety {
 
 #[async_trait]
 pub trait DriverStorage: dyn_clone::DynClone + private::Safety {
+    /// Loads a driver unless that would exceed `limits`, returns whether it was loaded. The
+    /// limits are checked in the same step as the insert, so concurrent loads can't exceed them.
     async fn insert(
         &self,
         driver_info: DriverInfo,
         module: wasmtime::component::Component,
-    ) -> anyhow::Result<()>;
+        manifest: Option<DriverManifest>,
+        limits: DriverLimits,
+    ) -> anyhow::Result<bool>;
     async fn get(
         &self,
         driver_info: &DriverInfo,
         engine: wasmtime::Engine,
     ) -> Result<Option<wasmtime::component::Component>, anyhow::Error>;
+    async fn manifest(&self, driver_info: &DriverInfo) -> anyhow::Result<Option<DriverManifest>>;
+    /// Loaded drivers, without compiling their components.
+    async fn list_info(&self) -> anyhow::Result<Vec<DriverInfo>>;
     async fn list(
         &self,
         engine: wasmtime::Engine,
@@ -71,6 +128,43 @@ pub trait UserStorage: dyn_clone::DynClone + private::Safety {
     async fn get(&self, username: &str, password: &str) -> anyhow::Result<Option<String>>;
 }
 
+#[async_trait]
+pub trait TransactionLog: dyn_clone::DynClone + private::Safety {
+    async fn upsert(&self, record: &TransactionRecord) -> anyhow::Result<()>;
+    async fn pending(&self) -> anyhow::Result<Vec<TransactionRecord>>;
+}
+
+/// Configuration of drivers by driver name, shared by every version of a driver.
+#[async_trait]
+pub trait DriverConfigStorage: dyn_clone::DynClone + private::Safety {
+    async fn set(&self, driver_name: &str, key: &str, entry: ConfigEntry) -> anyhow::Result<()>;
+    async fn get(&self, driver_name: &str, key: &str) -> anyhow::Result<Option<ConfigEntry>>;
+    async fn list(&self, driver_name: &str) -> anyhow::Result<Vec<(String, ConfigEntry)>>;
+    /// Removes `key`, returning whether it was set.
+    async fn remove(&self, driver_name: &str, key: &str) -> anyhow::Result<bool>;
+}
+
+/// Keys of drivers by driver name, every version of a key is kept so older signatures can still
+/// be checked.
+#[async_trait]
+pub trait KeyStorage: dyn_clone::DynClone + private::Safety {
+    /// Adds a version of a key, failing when that version already exists.
+    async fn insert(&self, record: &KeyRecord) -> anyhow::Result<()>;
+    async fn latest(&self, driver_name: &str, key_name: &str) -> anyhow::Result<Option<KeyRecord>>;
+    /// Every version of every key of a driver, by name and version.
+    async fn list(&self, driver_name: &str) -> anyhow::Result<Vec<KeyRecord>>;
+    async fn record_signature(&self, record: &SignatureRecord) -> anyhow::Result<()>;
+    /// Latest signatures made by a driver, most recent first.
+    /// Up to `limit` signatures of the driver, made for `user_id` when there is one, most recent
+    /// first.
+    async fn signatures(
+        &self,
+        driver_name: &str,
+        user_id: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<SignatureRecord>>;
+}
+
 #[async_trait]
 impl Resolver for PersistentStorage {
     async fn remove(&self, path: &str) -> Option<PathInfo> {
@@ -86,6 +180,16 @@ impl Resolver for PersistentStorage {
             .collect()
     }
 
+    async fn list_prefix(&self, prefix: &str) -> Vec<(String, PathInfo)> {
+        self.mount_points
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| path.starts_with(prefix))
+            .map(|(path, path_info)| (path.clone(), path_info.clone()))
+            .collect()
+    }
+
     async fn get(&self, path: &str) -> Option<PathInfo> {
         self.mount_points.read().ok()?.get(path).cloned()
     }
@@ -137,12 +241,33 @@ impl DriverStorage for PersistentStorage {
         &self,
         driver_info: DriverInfo,
         module: wasmtime::component::Component,
-    ) -> anyhow::Result<()> {
-        self.drivers
+        manifest: Option<DriverManifest>,
+        limits: DriverLimits,
+    ) -> anyhow::Result<bool> {
+        let mut drivers = self
+            .drivers
             .write()
-            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
-            .insert(driver_info, module);
-        Ok(())
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?;
+        if !drivers.contains_key(&driver_info) {
+            let versions = drivers
+                .keys()
+                .filter(|loaded| loaded.name == driver_info.name)
+                .count();
+            if drivers.len() >= limits.drivers as usize || versions >= limits.versions as usize {
+                return Ok(false);
+            }
+        }
+
+        let mut manifests = self
+            .manifests
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?;
+        match manifest {
+            Some(manifest) => manifests.insert(driver_info.clone(), manifest),
+            None => manifests.remove(&driver_info),
+        };
+        drivers.insert(driver_info, module);
+        Ok(true)
     }
     async fn get(
         &self,
@@ -170,7 +295,30 @@ impl DriverStorage for PersistentStorage {
             .collect())
     }
 
+    async fn manifest(&self, driver_info: &DriverInfo) -> anyhow::Result<Option<DriverManifest>> {
+        Ok(self
+            .manifests
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .get(driver_info)
+            .cloned())
+    }
+
+    async fn list_info(&self) -> anyhow::Result<Vec<DriverInfo>> {
+        Ok(self
+            .drivers
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .keys()
+            .cloned()
+            .collect())
+    }
+
     async fn remove(&self, driver_info: &DriverInfo) -> anyhow::Result<()> {
+        self.manifests
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .remove(driver_info);
         self.drivers
             .write()
             .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
@@ -207,6 +355,149 @@ impl UserStorage for PersistentStorage {
     }
 }
 
+#[async_trait]
+impl TransactionLog for PersistentStorage {
+    async fn upsert(&self, record: &TransactionRecord) -> anyhow::Result<()> {
+        self.transactions
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .insert(record.txn_id.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn pending(&self) -> anyhow::Result<Vec<TransactionRecord>> {
+        Ok(self
+            .transactions
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .values()
+            .filter(|record| !record.state.is_final())
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl DriverConfigStorage for PersistentStorage {
+    async fn set(&self, driver_name: &str, key: &str, entry: ConfigEntry) -> anyhow::Result<()> {
+        self.driver_config
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .entry(driver_name.to_string())
+            .or_default()
+            .insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    async fn get(&self, driver_name: &str, key: &str) -> anyhow::Result<Option<ConfigEntry>> {
+        Ok(self
+            .driver_config
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .get(driver_name)
+            .and_then(|config| config.get(key))
+            .cloned())
+    }
+
+    async fn list(&self, driver_name: &str) -> anyhow::Result<Vec<(String, ConfigEntry)>> {
+        let mut entries = self
+            .driver_config
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .get(driver_name)
+            .map(|config| {
+                config
+                    .iter()
+                    .map(|(key, entry)| (key.clone(), entry.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    async fn remove(&self, driver_name: &str, key: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .driver_config
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .get_mut(driver_name)
+            .and_then(|config| config.remove(key))
+            .is_some())
+    }
+}
+
+#[async_trait]
+impl KeyStorage for PersistentStorage {
+    async fn insert(&self, record: &KeyRecord) -> anyhow::Result<()> {
+        let mut keys = self
+            .keys
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?;
+        anyhow::ensure!(
+            !keys.iter().any(|key| key.driver_name == record.driver_name
+                && key.key_name == record.key_name
+                && key.version == record.version),
+            "Version {} of {} already exists",
+            record.version,
+            record.key_name
+        );
+        keys.push(record.clone());
+        Ok(())
+    }
+
+    async fn latest(&self, driver_name: &str, key_name: &str) -> anyhow::Result<Option<KeyRecord>> {
+        Ok(self
+            .keys
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .iter()
+            .filter(|key| key.driver_name == driver_name && key.key_name == key_name)
+            .max_by_key(|key| key.version)
+            .cloned())
+    }
+
+    async fn list(&self, driver_name: &str) -> anyhow::Result<Vec<KeyRecord>> {
+        let mut keys: Vec<_> = self
+            .keys
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .iter()
+            .filter(|key| key.driver_name == driver_name)
+            .cloned()
+            .collect();
+        keys.sort_by(|a, b| (&a.key_name, a.version).cmp(&(&b.key_name, b.version)));
+        Ok(keys)
+    }
+
+    async fn record_signature(&self, record: &SignatureRecord) -> anyhow::Result<()> {
+        self.signatures
+            .write()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .push(record.clone());
+        Ok(())
+    }
+
+    async fn signatures(
+        &self,
+        driver_name: &str,
+        user_id: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<SignatureRecord>> {
+        Ok(self
+            .signatures
+            .read()
+            .map_err(|e| anyhow::anyhow!("Poisoned Lock {:?}", e))?
+            .iter()
+            .rev()
+            .filter(|record| record.driver_name == driver_name)
+            .filter(|record| user_id.map_or(true, |user_id| record.user_id == user_id))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
 impl PersistentStorage {
     pub fn new() -> Self {
         Self::default()
@@ -217,5 +508,8 @@ dyn_clone::clone_trait_object!(Resolver);
 dyn_clone::clone_trait_object!(ProgramStorage);
 dyn_clone::clone_trait_object!(DriverStorage);
 dyn_clone::clone_trait_object!(UserStorage);
+dyn_clone::clone_trait_object!(TransactionLog);
+dyn_clone::clone_trait_object!(DriverConfigStorage);
+dyn_clone::clone_trait_object!(KeyStorage);
 
 pub mod sql;
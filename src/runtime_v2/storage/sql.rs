@@ -1,4 +1,10 @@
-use super::{DriverInfo, DriverStorage, PathInfo, Program, ProgramStorage, Resolver, UserStorage};
+use super::{
+    ConfigEntry, DriverConfigStorage, DriverInfo, DriverLimits, DriverManifest, DriverStorage,
+    KeyRecord, KeyStorage, PathInfo, Program, ProgramStorage, Resolver, SignatureRecord,
+    TransactionLog, TransactionRecord, UserStorage,
+};
+use crate::runtime_v2::coordinator::TransactionState;
+use crate::runtime_v2::keystore::KeyAlgorithm;
 use anyhow::{Context, Result};
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::SqlitePool;
@@ -66,6 +72,21 @@ impl Resolver for SqliteStorage {
             .collect()
     }
 
+    async fn list_prefix(&self, prefix: &str) -> Vec<(String, PathInfo)> {
+        // `substr` instead of `LIKE` so that `%` and `_` in paths are matched literally
+        sqlx::query!(
+            "SELECT path, path_info FROM Resolver WHERE substr(path, 1, length(?)) = ? ORDER BY path",
+            prefix,
+            prefix
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|row| Self::deserialize(&row.path_info).map(|info| (row.path, info)))
+        .collect()
+    }
+
     async fn get(&self, path: &str) -> Option<PathInfo> {
         let result = sqlx::query!("SELECT path_info FROM Resolver WHERE path = ?", path)
             .fetch_optional(&self.pool)
@@ -96,11 +117,12 @@ impl ProgramStorage for SqliteStorage {
         let component_bytes = Self::serialize_component(&program.component).await?;
 
         sqlx::query!(
-            "INSERT OR REPLACE INTO Program (id, name, version, component) VALUES (?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO Program (id, name, version, component, allow_bind) VALUES (?, ?, ?, ?, ?)",
             id,
             program.name,
             program.version,
-            component_bytes
+            component_bytes,
+            program.allow_bind
         )
         .execute(&self.pool)
         .await
@@ -111,7 +133,7 @@ impl ProgramStorage for SqliteStorage {
 
     async fn get(&self, id: &str, engine: wasmtime::Engine) -> Result<Option<Program>> {
         let result = sqlx::query!(
-            "SELECT name, version, component FROM Program WHERE id = ?",
+            r#"SELECT name, version, component, allow_bind as "allow_bind: bool" FROM Program WHERE id = ?"#,
             id
         )
         .fetch_optional(&self.pool)
@@ -123,15 +145,18 @@ impl ProgramStorage for SqliteStorage {
                     component: Self::deserialize_component(&row.component, &engine)?,
                     name: row.name,
                     version: row.version,
+                    allow_bind: row.allow_bind,
                 })
             })
             .transpose()
     }
 
     async fn list(&self, engine: wasmtime::Engine) -> Result<Vec<(String, Program)>> {
-        let rows = sqlx::query!("SELECT id, name, version, component FROM Program")
-            .fetch_all(&self.pool)
-            .await?;
+        let rows = sqlx::query!(
+            r#"SELECT id, name, version, component, allow_bind as "allow_bind: bool" FROM Program"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
 
         let mut programs = Vec::with_capacity(rows.len());
         for row in rows {
@@ -139,6 +164,7 @@ impl ProgramStorage for SqliteStorage {
                 component: Self::deserialize_component(&row.component, &engine)?,
                 name: row.name,
                 version: row.version,
+                allow_bind: row.allow_bind,
             };
             programs.push((row.id, program));
         }
@@ -149,20 +175,36 @@ impl ProgramStorage for SqliteStorage {
 
 #[async_trait]
 impl DriverStorage for SqliteStorage {
-    async fn insert(&self, driver_info: DriverInfo, module: Component) -> Result<()> {
+    async fn insert(
+        &self,
+        driver_info: DriverInfo,
+        module: Component,
+        manifest: Option<DriverManifest>,
+        limits: DriverLimits,
+    ) -> Result<bool> {
         let component_bytes = Self::serialize_component(&module).await?;
+        let manifest = manifest
+            .map(|manifest| serde_json::to_string(&manifest))
+            .transpose()?;
 
-        sqlx::query!(
-            "INSERT OR REPLACE INTO Driver (name, version, component) VALUES (?, ?, ?)",
+        // a single statement, the counts can't change between the check and the insert
+        let result = sqlx::query!(
+            "INSERT OR REPLACE INTO Driver (name, version, component, manifest) SELECT ?, ?, ?, ? WHERE EXISTS (SELECT 1 FROM Driver WHERE name = ? AND version = ?) OR ((SELECT COUNT(*) FROM Driver) < ? AND (SELECT COUNT(*) FROM Driver WHERE name = ?) < ?)",
+            driver_info.name,
+            driver_info.version,
+            component_bytes,
+            manifest,
             driver_info.name,
             driver_info.version,
-            component_bytes
+            limits.drivers,
+            driver_info.name,
+            limits.versions
         )
         .execute(&self.pool)
         .await
         .context("Failed to insert driver")?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     async fn get(
@@ -201,6 +243,35 @@ impl DriverStorage for SqliteStorage {
         Ok(drivers)
     }
 
+    async fn manifest(&self, driver_info: &DriverInfo) -> Result<Option<DriverManifest>> {
+        let result = sqlx::query!(
+            "SELECT manifest FROM Driver WHERE name = ? AND version = ?",
+            driver_info.name,
+            driver_info.version
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        result
+            .and_then(|row| row.manifest)
+            .map(|manifest| serde_json::from_str(&manifest).context("Invalid driver manifest"))
+            .transpose()
+    }
+
+    async fn list_info(&self) -> Result<Vec<DriverInfo>> {
+        let rows = sqlx::query!("SELECT name, version FROM Driver")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DriverInfo {
+                name: row.name,
+                version: row.version,
+            })
+            .collect())
+    }
+
     async fn remove(&self, driver_info: &DriverInfo) -> Result<()> {
         sqlx::query!(
             "DELETE FROM Driver WHERE name = ? AND version = ?",
@@ -246,3 +317,232 @@ impl UserStorage for SqliteStorage {
             .ok_or_else(|| anyhow::anyhow!("User not found"))
     }
 }
+
+#[async_trait]
+impl TransactionLog for SqliteStorage {
+    async fn upsert(&self, record: &TransactionRecord) -> Result<()> {
+        let participants = serde_json::to_string(&record.participants)
+            .context("Failed to serialize participants")?;
+        let state = record.state.as_str();
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO TransactionLog (txn_id, user_id, participants, state, updated_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))",
+            record.txn_id,
+            record.user_id,
+            participants,
+            state
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to write transaction log")?;
+
+        Ok(())
+    }
+
+    async fn pending(&self) -> Result<Vec<TransactionRecord>> {
+        let rows = sqlx::query!(
+            "SELECT txn_id, user_id, participants, state FROM TransactionLog WHERE state NOT IN ('committed', 'aborted') ORDER BY updated_at"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(TransactionRecord {
+                    txn_id: row.txn_id,
+                    user_id: row.user_id,
+                    participants: serde_json::from_str(&row.participants)
+                        .context("Failed to deserialize participants")?,
+                    state: TransactionState::parse(&row.state)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl DriverConfigStorage for SqliteStorage {
+    async fn set(&self, driver_name: &str, key: &str, entry: ConfigEntry) -> Result<()> {
+        sqlx::query!(
+            "INSERT OR REPLACE INTO DriverConfig (driver_name, key, value, secret, updated_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))",
+            driver_name,
+            key,
+            entry.value,
+            entry.secret
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to write driver config")?;
+
+        Ok(())
+    }
+
+    async fn get(&self, driver_name: &str, key: &str) -> Result<Option<ConfigEntry>> {
+        let row = sqlx::query!(
+            "SELECT value, secret FROM DriverConfig WHERE driver_name = ? AND key = ?",
+            driver_name,
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| ConfigEntry {
+            value: row.value,
+            secret: row.secret,
+        }))
+    }
+
+    async fn list(&self, driver_name: &str) -> Result<Vec<(String, ConfigEntry)>> {
+        let rows = sqlx::query!(
+            "SELECT key, value, secret FROM DriverConfig WHERE driver_name = ? ORDER BY key",
+            driver_name
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.key,
+                    ConfigEntry {
+                        value: row.value,
+                        secret: row.secret,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    async fn remove(&self, driver_name: &str, key: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM DriverConfig WHERE driver_name = ? AND key = ?",
+            driver_name,
+            key
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl KeyStorage for SqliteStorage {
+    async fn insert(&self, record: &KeyRecord) -> Result<()> {
+        let algorithm = record.algorithm.as_str();
+        let created_at = record.created_at as i64;
+        sqlx::query!(
+            "INSERT INTO DriverKeys (driver_name, key_name, version, algorithm, public_key, private_key, created_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            record.driver_name,
+            record.key_name,
+            record.version,
+            algorithm,
+            record.public_key,
+            record.private_key,
+            created_at
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to write driver key")?;
+
+        Ok(())
+    }
+
+    async fn latest(&self, driver_name: &str, key_name: &str) -> Result<Option<KeyRecord>> {
+        let row = sqlx::query!(
+            "SELECT driver_name, key_name, version, algorithm, public_key, private_key, created_at FROM DriverKeys WHERE driver_name = ? AND key_name = ? ORDER BY version DESC LIMIT 1",
+            driver_name,
+            key_name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| {
+            Ok(KeyRecord {
+                driver_name: row.driver_name,
+                key_name: row.key_name,
+                version: row.version as u32,
+                algorithm: KeyAlgorithm::parse(&row.algorithm)?,
+                public_key: row.public_key,
+                private_key: row.private_key,
+                created_at: row.created_at as u64,
+            })
+        })
+        .transpose()
+    }
+
+    async fn list(&self, driver_name: &str) -> Result<Vec<KeyRecord>> {
+        let rows = sqlx::query!(
+            "SELECT driver_name, key_name, version, algorithm, public_key, private_key, created_at FROM DriverKeys WHERE driver_name = ? ORDER BY key_name, version",
+            driver_name
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(KeyRecord {
+                    driver_name: row.driver_name,
+                    key_name: row.key_name,
+                    version: row.version as u32,
+                    algorithm: KeyAlgorithm::parse(&row.algorithm)?,
+                    public_key: row.public_key,
+                    private_key: row.private_key,
+                    created_at: row.created_at as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn record_signature(&self, record: &SignatureRecord) -> Result<()> {
+        let signed_at = record.signed_at as i64;
+        sqlx::query!(
+            "INSERT INTO KeySignatures (driver_name, driver_version, key_name, key_version, user_id, execution_id, payload_hash, signed_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            record.driver_name,
+            record.driver_version,
+            record.key_name,
+            record.key_version,
+            record.user_id,
+            record.execution_id,
+            record.payload_hash,
+            signed_at
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to write signature record")?;
+
+        Ok(())
+    }
+
+    async fn signatures(
+        &self,
+        driver_name: &str,
+        user_id: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<SignatureRecord>> {
+        let rows = sqlx::query!(
+            "SELECT driver_name, driver_version, key_name, key_version, user_id, execution_id, payload_hash, signed_at FROM KeySignatures WHERE driver_name = ? AND (? IS NULL OR user_id = ?) ORDER BY id DESC LIMIT ?",
+            driver_name,
+            user_id,
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SignatureRecord {
+                driver_name: row.driver_name,
+                driver_version: row.driver_version,
+                key_name: row.key_name,
+                key_version: row.key_version as u32,
+                user_id: row.user_id,
+                execution_id: row.execution_id,
+                payload_hash: row.payload_hash,
+                signed_at: row.signed_at as u64,
+            })
+            .collect())
+    }
+}
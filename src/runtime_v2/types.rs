@@ -1,9 +1,23 @@
-use std::collections::HashMap;
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
+
+use wasmtime::component::Resource;
+use wasmtime_wasi::{ResourceTable, WasiCtx};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperIncomingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request_handler, HostFutureIncomingResponse, OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 
 use super::driver::{self, DriverInfo};
+use super::keystore::Keyring;
+use super::logging::GuestLogs;
+use super::manifest::DriverManifest;
+use super::platform::clock::Clock;
+use super::platform::random::Random;
 use super::platform::Platform;
 use super::resolver::PathInfo;
+use super::sandbox::Sandbox;
 
 pub mod component {
     pub mod driver {
@@ -15,12 +29,42 @@ pub mod component {
         });
     }
 
+    pub mod transactional {
+        wasmtime::component::bindgen!({
+            world: "transactional-driver-world",
+            path: "wit",
+            tracing: true,
+            async: true,
+        });
+    }
+
+    pub mod assets {
+        wasmtime::component::bindgen!({
+            world: "asset-driver-world",
+            path: "wit",
+            tracing: true,
+            async: true,
+        });
+    }
+
+    pub mod callback {
+        wasmtime::component::bindgen!({
+            world: "callback-driver-world",
+            path: "wit",
+            tracing: true,
+            async: true,
+        });
+    }
+
     pub mod module {
         wasmtime::component::bindgen!({
             world: "module-world",
             path: "wit",
             tracing: true,
             async: true,
+            with: {
+                "component:units/descriptors/descriptor": crate::runtime_v2::types::Descriptor,
+            },
         });
     }
 }
@@ -28,11 +72,51 @@ pub mod component {
 #[derive(Clone)]
 pub struct UserCtx {
     pub user_id: String,
+    /// Identifies everything done on behalf of a single request in the logs.
+    pub execution_id: String,
+}
+
+impl UserCtx {
+    pub fn new(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            execution_id: crate::utils::id::new(),
+        }
+    }
+
+    /// Root of the paths owned by the user, this is what `~/` expands to.
+    pub fn home(&self) -> String {
+        format!("/accounts/{}/", self.user_id)
+    }
+
+    /// Expands a leading `~/` in `path` to the user's home.
+    pub fn expand_path(&self, path: &str) -> String {
+        match path.strip_prefix("~/") {
+            Some(suffix) => format!("{}{}", self.home(), suffix),
+            None => path.to_string(),
+        }
+    }
+}
+
+/// What a program is allowed to do besides using the paths it is handed, decided when the
+/// program is submitted.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramPolicy {
+    pub allow_bind: bool,
 }
 
 #[derive(Clone)]
 pub struct DriverCtx {
     pub driver_info: DriverInfo,
+    /// Path the driver is called for, if the call is made on one.
+    pub path: Option<String>,
+    pub sandbox: Sandbox,
+    pub config: driver::ConfigReader,
+    pub keys: Keyring,
+    pub logs: GuestLogs,
+    /// Clock and randomness of the execution the driver is called in.
+    pub clock: Clock,
+    pub random: Random,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -44,14 +128,320 @@ pub struct RuntimeConfig {
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct DriverConfig {
     pub driver_limit: u32,
-    pub driver_timeout: u32, // in seconds
+    /// Seconds a single call into a driver may take, checked whenever the driver yields to
+    /// the host.
+    pub driver_timeout: u32,
+    /// Versions of a single driver that can be loaded at the same time.
+    #[serde(default = "default_max_versions")]
+    pub max_versions: u32,
+    /// Largest driver binary accepted by `LoadDriver`, in bytes.
+    #[serde(default = "default_max_component_size")]
+    pub max_component_size: usize,
+    /// Calls a single driver version serves per minute, `0` disables the limit.
+    #[serde(default)]
+    pub calls_per_minute: u32,
+    /// Time a driver's http request can take, including reading the response.
+    #[serde(default = "default_http_timeout_ms")]
+    pub http_timeout_ms: u64,
+    /// Largest http response a driver can read, in bytes.
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: usize,
+    /// Delay between retries of transaction decisions a participant didn't acknowledge.
+    #[serde(default = "default_transaction_retry_secs")]
+    pub transaction_retry_secs: u64,
+    /// Endpoints granted to drivers by name, on top of the ones their manifest requests.
+    #[serde(default)]
+    pub http_allowlist: std::collections::HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Environment variable holding the key material driver secrets are encrypted with.
+    #[serde(default = "default_secret_key_env")]
+    pub secret_key_env: String,
+    /// Environment variable holding the token admin calls carry in `x-admin-token`.
+    #[serde(default = "default_admin_token_env")]
+    pub admin_token_env: String,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Drivers that receive callbacks at `/hooks/{driver}/{version}/...`, by name.
+    #[serde(default)]
+    pub webhooks: std::collections::HashMap<String, WebhookConfig>,
+}
+
+/// Logs of programs and drivers, see [`GuestLogs`].
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LoggingConfig {
+    /// Lowest level logged for programs and for drivers without a level of their own.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Levels by driver name.
+    #[serde(default)]
+    pub levels: std::collections::HashMap<String, String>,
+    /// Logs a driver version, or the programs of a user, can emit per minute, the rest are
+    /// dropped. `0` disables the limit.
+    #[serde(default = "default_logs_per_minute")]
+    pub logs_per_minute: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            levels: Default::default(),
+            logs_per_minute: default_logs_per_minute(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WebhookConfig {
+    /// Header carrying the hex encoded HMAC-SHA256 of the body. Callbacks without one are
+    /// refused, unless the webhook is `insecure`.
+    #[serde(default)]
+    pub signature_header: Option<String>,
+    /// Key of the driver config holding the HMAC secret, set with `SetDriverConfig`.
+    #[serde(default = "default_webhook_secret")]
+    pub secret: String,
+    /// Delivers callbacks without a `signature_header` unverified, anyone reaching the server
+    /// can then call the driver.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct StorageConfig {
+    /// Drivers whose keys are scoped by version as well, every version gets its own keys.
+    #[serde(default)]
+    pub version_scoped: Vec<String>,
+    /// Namespaces shared between drivers, by the names of the drivers they are shared with.
+    #[serde(default)]
+    pub shared: std::collections::HashMap<String, Vec<String>>,
+}
+
+fn default_max_versions() -> u32 {
+    10
+}
+
+fn default_max_component_size() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_http_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_max_response_size() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_transaction_retry_secs() -> u64 {
+    30
+}
+
+fn default_secret_key_env() -> String {
+    "UNITS_SECRET_KEY".to_string()
+}
+
+fn default_admin_token_env() -> String {
+    "UNITS_ADMIN_TOKEN".to_string()
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_logs_per_minute() -> u32 {
+    600
+}
+
+fn default_webhook_secret() -> String {
+    "webhook_secret".to_string()
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct ProcessConfig {}
 
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct PlatformConfig {
+    /// Pins the time guests see, for tests and replays.
+    #[serde(default)]
+    pub clock: Option<PinnedClock>,
+    /// Seeds the randomness guests see, for tests and replays.
+    #[serde(default)]
+    pub random_seed: Option<u64>,
+    /// Backend of the storage drivers read and write.
+    #[serde(default)]
+    pub storage: KvConfig,
+    /// Keys sent somewhere else than `storage`, by prefix.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Where settlements wait to be delivered, along with the keys they settle.
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct OutboxConfig {
+    /// SQLite database the settlements and the keys of settlement routes are kept in, in memory
+    /// when unset.
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default = "default_outbox_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Attempts at delivering a settlement before it is marked as failed.
+    #[serde(default = "default_outbox_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry of a settlement, doubled on every retry after it.
+    #[serde(default = "default_outbox_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+    #[serde(default = "default_outbox_max_retry_delay_secs")]
+    pub max_retry_delay_secs: u64,
+    /// How long a settlement stays claimed by the worker delivering it. Should outlast the
+    /// deliveries, a settlement whose lease runs out is claimed again.
+    #[serde(default = "default_outbox_lease_secs")]
+    pub lease_secs: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            poll_interval_ms: default_outbox_poll_interval_ms(),
+            max_attempts: default_outbox_max_attempts(),
+            retry_delay_secs: default_outbox_retry_delay_secs(),
+            max_retry_delay_secs: default_outbox_max_retry_delay_secs(),
+            lease_secs: default_outbox_lease_secs(),
+        }
+    }
+}
+
+fn default_outbox_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_outbox_max_attempts() -> u32 {
+    10
+}
+
+fn default_outbox_retry_delay_secs() -> u64 {
+    5
+}
+
+fn default_outbox_max_retry_delay_secs() -> u64 {
+    600
+}
+
+fn default_outbox_lease_secs() -> u64 {
+    120
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum KvConfig {
+    /// Kept in memory and lost on restart.
+    #[default]
+    Memory,
+    Sqlite {
+        #[serde(default = "default_sqlite_url")]
+        url: String,
+    },
+    Redis(RedisConfig),
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct RedisConfig {
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+    #[serde(default = "default_redis_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// Longest wait for the answer to a command.
+    #[serde(default = "default_redis_timeout_ms")]
+    pub response_timeout_ms: u64,
+    /// Retries of commands that failed to reach redis, and of reconnecting.
+    #[serde(default = "default_redis_retries")]
+    pub retries: u32,
+}
+
+/// Route of the keys starting with `prefix`, after their namespace. Keys go to the route with
+/// the longest matching prefix, the others to the platform storage.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct RouteConfig {
+    pub prefix: String,
+    pub sink: SinkConfig,
+    /// Defaults to `sink` for mirrors, `store` otherwise.
+    #[serde(default)]
+    pub read: Option<ReadMode>,
+    /// Defaults to `deny` for mirrors, `both` for settlements and webhooks, `store` for kv.
+    /// Settlements can't be `sink`, they are queued along with the value they settle.
+    #[serde(default)]
+    pub write: Option<WriteMode>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// Keys kept in a backend of their own.
+    Kv { store: KvConfig },
+    /// Writes queued in the outbox along with the value, then delivered to a settlement
+    /// integration, `solana`.
+    Settlement { integration: String },
+    /// Writes posted as json to a url.
+    Webhook {
+        url: String,
+        #[serde(default = "default_webhook_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Reads answered from a backend kept up to date elsewhere.
+    Mirror { store: KvConfig },
+}
+
+/// Where the keys of a route are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadMode {
+    Store,
+    Sink,
+}
+
+/// Where the keys of a route are written to. Only `set` goes to a `sink` alone, other updates
+/// need the current value and are denied like on `deny`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    Store,
+    Sink,
+    /// To the store, then to the sink.
+    Both,
+    Deny,
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_sqlite_url() -> String {
+    "sqlite:units.db".to_string()
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1/".to_string()
+}
+
+fn default_redis_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_redis_retries() -> u32 {
+    3
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
-pub struct PlatformConfig {}
+pub struct PinnedClock {
+    /// Unix time the clock starts at, in seconds.
+    pub start: u64,
+    /// Milliseconds the clock moves by on every reading.
+    #[serde(default)]
+    pub step_ms: u64,
+}
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Event {
@@ -95,19 +485,287 @@ pub enum Loc {
 
 pub struct ProcessState {
     pub ctx: UserCtx,
+    pub policy: ProgramPolicy,
     pub driver_runtime: driver::DriverRuntime,
     pub platform: Platform,
     pub event_sender: Arc<mpsc::Sender<Event>>,
-    pub descriptors: HashMap<String, Descriptor>,
-    // table: ResourceTable,
-    // wasi_ctx: WasiCtx,
+    // only ever reached through `&mut self`, the mutexes are never locked and are there to keep
+    // the state `Sync`
+    table: Mutex<ResourceTable>,
+    /// Serial of the next descriptor opened.
+    pub descriptor_serial: u64,
+    wasi_ctx: Mutex<WasiCtx>,
+    pub clock: Clock,
+    pub random: Random,
+    /// Driver failures handed back to the program during the execution. The execution is only
+    /// reported to have failed with one when the program propagated it.
+    pub failures: Vec<DriverFailure>,
 }
 
+/// Descriptor kept in the resource table of its execution. Its key is `{rep}.{serial}`, the
+/// serial keeps a key kept after the descriptor is done from reaching one that took its slot.
 #[derive(Clone)]
 pub struct Descriptor {
+    pub path: String,
     pub driver_name: String,
     pub driver_version: String,
     pub account_info: serde_json::Value,
+    pub serial: u64,
+    /// Opened as a resource, the entry then stays in the table after `done` until the handle
+    /// is dropped, so its slot isn't reused while the program holds the handle.
+    pub handle: bool,
+    pub closed: bool,
+}
+
+impl Descriptor {
+    /// Descriptor of `key` in `table`, unless it is done.
+    pub fn find<'a>(
+        table: &'a ResourceTable,
+        key: &str,
+    ) -> Result<&'a Descriptor, component::module::component::units::driver::DriverError> {
+        let (resource, serial) = Self::resource(key)?;
+        match table.get(&resource) {
+            Ok(descriptor) if descriptor.serial == serial && !descriptor.closed => Ok(descriptor),
+            _ => Err(Self::not_found()),
+        }
+    }
+
+    /// Removes the descriptor of `key` from `table`, or only closes it while its handle is held.
+    pub fn remove(
+        table: &mut ResourceTable,
+        key: &str,
+    ) -> Result<(), component::module::component::units::driver::DriverError> {
+        let handle = Self::find(table, key)?.handle;
+        let (resource, _) = Self::resource(key)?;
+        let removed = match handle {
+            true => table
+                .get_mut(&resource)
+                .map(|descriptor| descriptor.closed = true),
+            false => table.delete(resource).map(|_| ()),
+        };
+        removed.map_err(|_| Self::not_found())
+    }
+
+    fn resource(
+        key: &str,
+    ) -> Result<(Resource<Descriptor>, u64), component::module::component::units::driver::DriverError>
+    {
+        key.split_once('.')
+            .and_then(|(rep, serial)| Some((rep.parse().ok()?, serial.parse().ok()?)))
+            .map(|(rep, serial)| (Resource::new_own(rep), serial))
+            .ok_or_else(|| {
+                component::module::component::units::driver::DriverError::InvalidInput(
+                    "Invalid descriptor".to_string(),
+                )
+            })
+    }
+
+    fn not_found() -> component::module::component::units::driver::DriverError {
+        component::module::component::units::driver::DriverError::InvalidInput(
+            "Failed while finding descriptor".to_string(),
+        )
+    }
+}
+
+/// Variant of the `driver-error` a driver call failed with, `Trap` is used when the driver never
+/// got to return an error, e.g. it failed to instantiate or panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    PermissionDenied,
+    SystemError,
+    InvalidInput,
+    UnknownError,
+    Trap,
+    /// The call was refused before reaching the driver, it used up its call-rate limit.
+    Throttled,
+    /// The driver didn't return within `driver_timeout`.
+    DeadlineExceeded,
+}
+
+impl FailureKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PermissionDenied => "permission-denied",
+            Self::SystemError => "system-error",
+            Self::InvalidInput => "invalid-input",
+            Self::UnknownError => "unknown-error",
+            Self::Trap => "trap",
+            Self::Throttled => "throttled",
+            Self::DeadlineExceeded => "deadline-exceeded",
+        }
+    }
+}
+
+/// A failed call into a driver, with the variant and message the driver returned and where the
+/// call was made.
+#[derive(Debug, Clone)]
+pub struct DriverFailure {
+    pub driver_info: DriverInfo,
+    pub path: Option<String>,
+    pub operation: &'static str,
+    pub kind: FailureKind,
+    pub message: String,
+    /// Set when the failure is handed back to the program, which then finds it in its error
+    /// as `[failure:{id}]`.
+    pub id: Option<String>,
+}
+
+impl std::fmt::Display for DriverFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}@{} failed on {}",
+            self.driver_info.name, self.driver_info.version, self.operation
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " for {}", path)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for DriverFailure {}
+
+impl DriverFailure {
+    /// Marker the error handed back to the program carries. It is left alone by the ways
+    /// programs usually pass errors on, wrapping them or rendering them with `{:?}`.
+    pub fn marker(&self) -> Option<String> {
+        self.id.as_ref().map(|id| format!("[failure:{}]", id))
+    }
+
+    /// The error handed back to the program, keeps the variant the driver returned.
+    pub fn to_driver_error(&self) -> component::module::component::units::driver::DriverError {
+        use component::module::component::units::driver::DriverError;
+
+        let message = match self.marker() {
+            Some(marker) => format!("{} {}", self, marker),
+            None => self.to_string(),
+        };
+        match self.kind {
+            FailureKind::PermissionDenied => DriverError::PermissionDenied(message),
+            FailureKind::InvalidInput => DriverError::InvalidInput(message),
+            FailureKind::UnknownError => DriverError::UnknownError(message),
+            FailureKind::SystemError
+            | FailureKind::Trap
+            | FailureKind::Throttled
+            | FailureKind::DeadlineExceeded => DriverError::SystemError(message),
+        }
+    }
+}
+
+/// Errors returned by the exports of a driver, across the worlds a driver can be loaded as.
+pub trait LowerDriverError {
+    fn into_parts(self) -> (FailureKind, String);
+}
+
+macro_rules! lower_driver_error {
+    ($error:ty) => {
+        impl LowerDriverError for $error {
+            fn into_parts(self) -> (FailureKind, String) {
+                match self {
+                    Self::PermissionDenied(message) => (FailureKind::PermissionDenied, message),
+                    Self::SystemError(message) => (FailureKind::SystemError, message),
+                    Self::InvalidInput(message) => (FailureKind::InvalidInput, message),
+                    Self::UnknownError(message) => (FailureKind::UnknownError, message),
+                }
+            }
+        }
+    };
+}
+
+lower_driver_error!(component::driver::exports::component::units::driver::DriverError);
+lower_driver_error!(component::transactional::exports::component::units::driver::DriverError);
+lower_driver_error!(component::callback::exports::component::units::driver::DriverError);
+lower_driver_error!(component::assets::exports::component::units::driver::DriverError);
+
+/// Export of the drivers that take typed asset values.
+pub const ASSETS_EXPORT: &str = "component:units/assets";
+
+/// Whether `driver` exports the interface `name`, optional extensions are detected this way.
+pub fn exports(
+    driver: &wasmtime::component::Component,
+    engine: &wasmtime::Engine,
+    name: &str,
+) -> bool {
+    driver
+        .component_type()
+        .exports(engine)
+        .any(|(export, _)| export == name)
+}
+
+/// Where a call into a driver is made, used to annotate whatever goes wrong during it.
+#[derive(Debug, Clone)]
+pub struct DriverCall {
+    pub driver_info: DriverInfo,
+    pub path: Option<String>,
+    pub operation: &'static str,
+}
+
+impl DriverCall {
+    pub fn new(driver_info: DriverInfo, path: Option<String>, operation: &'static str) -> Self {
+        Self {
+            driver_info,
+            path,
+            operation,
+        }
+    }
+
+    /// Call made on the driver backing `descriptor`.
+    pub fn on(descriptor: &Descriptor, operation: &'static str) -> Self {
+        Self::new(
+            DriverInfo {
+                name: descriptor.driver_name.clone(),
+                version: descriptor.driver_version.clone(),
+            },
+            Some(descriptor.path.clone()),
+            operation,
+        )
+    }
+
+    pub fn failure(&self, kind: FailureKind, message: impl Into<String>) -> DriverFailure {
+        DriverFailure {
+            driver_info: self.driver_info.clone(),
+            path: self.path.clone(),
+            operation: self.operation,
+            kind,
+            message: message.into(),
+            id: None,
+        }
+    }
+
+    /// Flattens the outcome of calling an export of the driver, a trap is kept apart from the
+    /// errors the driver returned.
+    pub fn check<T, E: LowerDriverError>(
+        &self,
+        result: wasmtime::Result<Result<T, E>>,
+    ) -> Result<T, Box<DriverFailure>> {
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(error)) => {
+                let (kind, message) = error.into_parts();
+                Err(Box::new(self.failure(kind, message)))
+            }
+            Err(trap) if trap.is::<tokio::time::error::Elapsed>() => Err(Box::new(self.failure(
+                FailureKind::DeadlineExceeded,
+                "Driver didn't return within its deadline",
+            ))),
+            Err(trap) => Err(Box::new(
+                self.failure(FailureKind::Trap, format!("{:#}", trap)),
+            )),
+        }
+    }
+}
+
+/// Bounds a call into a driver by `timeout`, a call that runs out of time fails with the
+/// `Elapsed` that [`DriverCall::check`] reports as a deadline exceeded.
+pub async fn with_deadline<T>(
+    timeout: std::time::Duration,
+    call: impl std::future::Future<Output = wasmtime::Result<T>>,
+) -> wasmtime::Result<T> {
+    match tokio::time::timeout(timeout, call).await {
+        Ok(result) => result,
+        Err(elapsed) => Err(elapsed.into()),
+    }
 }
 
 pub struct DriverState {
@@ -115,8 +773,11 @@ pub struct DriverState {
     pub driver_ctx: DriverCtx,
     pub platform: Platform,
     pub event_sender: Arc<mpsc::Sender<Event>>,
-    // table: ResourceTable,
-    // wasi_ctx: WasiCtx,
+    // only ever reached through `&mut self`, the mutexes are never locked and are there to keep
+    // the state `Sync`
+    table: Mutex<ResourceTable>,
+    wasi_ctx: Mutex<WasiCtx>,
+    http_ctx: WasiHttpCtx,
 }
 
 impl ProcessState {
@@ -126,17 +787,187 @@ impl ProcessState {
         platform: Platform,
         event_sender: Arc<mpsc::Sender<Event>>,
     ) -> Self {
+        let clock = platform.clock();
+        let mut random = platform.random();
         Self {
             ctx,
+            policy: ProgramPolicy::default(),
             driver_runtime,
             platform,
             event_sender,
-            descriptors: HashMap::new(),
-            // table: ResourceTable::new(),
-            // wasi_ctx: wasmtime_wasi::WasiCtxBuilder::new().build(),
+            table: Mutex::new(ResourceTable::new()),
+            descriptor_serial: 0,
+            wasi_ctx: Mutex::new(guest_wasi_ctx(&clock, &mut random)),
+            clock,
+            random,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Records the failure of a driver call and returns the error to hand back to the program.
+    pub fn fail(
+        &mut self,
+        mut failure: DriverFailure,
+    ) -> component::module::component::units::driver::DriverError {
+        tracing::error!(
+            runtime = "process",
+            driver = failure.driver_info.name.as_str(),
+            version = failure.driver_info.version.as_str(),
+            path = failure.path.as_deref(),
+            operation = failure.operation,
+            kind = failure.kind.as_str(),
+            message = failure.message.as_str(),
+            "driver call failed"
+        );
+
+        failure.id = Some(crate::utils::id::new());
+        let error = failure.to_driver_error();
+        self.failures.push(failure);
+        error
+    }
+
+    /// Takes the latest failure whose marker is in `message`, the error the program failed
+    /// with. Failures the program handled don't show up in its error and are never reported.
+    pub fn propagated_failure(&mut self, message: &str) -> Option<DriverFailure> {
+        let index = self.failures.iter().rposition(|failure| {
+            failure
+                .marker()
+                .is_some_and(|marker| message.contains(&marker))
+        })?;
+
+        Some(self.failures.remove(index))
+    }
+
+    /// Failure behind `error`, when it is what a driver call failed with.
+    pub fn failure_of(
+        &mut self,
+        error: &component::module::component::units::driver::DriverError,
+    ) -> Option<DriverFailure> {
+        use component::module::component::units::driver::DriverError;
+
+        match error {
+            DriverError::PermissionDenied(message)
+            | DriverError::SystemError(message)
+            | DriverError::InvalidInput(message)
+            | DriverError::UnknownError(message) => self.propagated_failure(message),
         }
     }
 
+    /// Counts `call` against the call-rate limit of its driver.
+    pub fn admit(
+        &mut self,
+        call: &DriverCall,
+    ) -> Result<(), component::module::component::units::driver::DriverError> {
+        let limiter = &self.driver_runtime.rate_limiter;
+        match limiter.acquire(&call.driver_info) {
+            Ok(()) => Ok(()),
+            Err(retry_after) => {
+                let message = format!(
+                    "Call rate limit of {} per minute reached, retry in {}s",
+                    limiter.limit(),
+                    retry_after.as_secs() + 1
+                );
+                Err(self.fail(call.failure(FailureKind::Throttled, message)))
+            }
+        }
+    }
+
+    /// Loads and instantiates the driver `call` is made on, failures are recorded against it.
+    pub async fn instantiate_driver(
+        &mut self,
+        call: &DriverCall,
+    ) -> Result<
+        (component::driver::DriverWorld, wasmtime::Store<DriverState>),
+        component::module::component::units::driver::DriverError,
+    > {
+        self.admit(call)?;
+        let (driver, linker, mut store) = self.load_driver(call).await?;
+
+        match component::driver::DriverWorld::instantiate_async(&mut store, &driver, &linker).await
+        {
+            Ok(instance) => Ok((instance, store)),
+            Err(e) => Err(self.fail(call.failure(
+                FailureKind::Trap,
+                format!("Failed while instantiating driver: {:#}", e),
+            ))),
+        }
+    }
+
+    /// Compiled driver `call` is made on, with a linker and store set up for it. The caller
+    /// picks the world to instantiate it as.
+    pub async fn load_driver(
+        &mut self,
+        call: &DriverCall,
+    ) -> Result<
+        (
+            wasmtime::component::Component,
+            wasmtime::component::Linker<DriverState>,
+            wasmtime::Store<DriverState>,
+        ),
+        component::module::component::units::driver::DriverError,
+    > {
+        let manifest = self.manifest(&call.driver_info).await?;
+        if !manifest.allows(call.operation) {
+            return Err(self.fail(call.failure(
+                FailureKind::InvalidInput,
+                format!("Driver does not support {}", call.operation),
+            )));
+        }
+        self.load_driver_ungated(call).await
+    }
+
+    /// [`Self::load_driver`] without checking the operation against the manifest, for calls the
+    /// host makes on its own behalf.
+    pub async fn load_driver_ungated(
+        &mut self,
+        call: &DriverCall,
+    ) -> Result<
+        (
+            wasmtime::component::Component,
+            wasmtime::component::Linker<DriverState>,
+            wasmtime::Store<DriverState>,
+        ),
+        component::module::component::units::driver::DriverError,
+    > {
+        let driver = match self
+            .get_driver(&call.driver_info, self.driver_runtime.engine.clone())
+            .await
+        {
+            Ok(driver) => driver,
+            Err(_) => {
+                return Err(self.fail(call.failure(FailureKind::InvalidInput, "Driver not found")))
+            }
+        };
+        let sandbox = match self.driver_runtime.sandbox(&call.driver_info).await {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                return Err(self.fail(call.failure(
+                    FailureKind::SystemError,
+                    format!("Failed while building sandbox: {:#}", e),
+                )))
+            }
+        };
+        let (linker, store) = self.get_lower_runtime(call, sandbox)?;
+
+        Ok((driver, linker, store))
+    }
+
+    /// Manifest the driver was loaded with, an empty one when it was loaded without.
+    pub async fn manifest(
+        &self,
+        driver_info: &DriverInfo,
+    ) -> Result<DriverManifest, component::module::component::units::driver::DriverError> {
+        Ok(self
+            .driver_runtime
+            .drivers
+            .manifest(driver_info)
+            .await
+            .map_err(|e| {
+                component::module::component::units::driver::DriverError::SystemError(e.to_string())
+            })?
+            .unwrap_or_default())
+    }
+
     pub async fn get_path_info(
         &self,
         input: String,
@@ -183,7 +1014,8 @@ impl ProcessState {
 
     pub fn get_lower_runtime(
         &self,
-        driver_info: DriverInfo,
+        call: &DriverCall,
+        sandbox: Sandbox,
     ) -> Result<
         (
             wasmtime::component::Linker<DriverState>,
@@ -195,7 +1027,16 @@ impl ProcessState {
             &self.driver_runtime.engine,
             DriverState::new(
                 self.ctx.clone(),
-                DriverCtx { driver_info },
+                DriverCtx {
+                    driver_info: call.driver_info.clone(),
+                    path: call.path.clone(),
+                    sandbox,
+                    config: self.driver_runtime.config_reader(&call.driver_info),
+                    keys: self.driver_runtime.keyring(&call.driver_info),
+                    logs: self.driver_runtime.logs.clone(),
+                    clock: self.clock.clone(),
+                    random: self.random.clone(),
+                },
                 self.platform.clone(),
                 self.event_sender.clone(),
             ),
@@ -207,38 +1048,46 @@ impl ProcessState {
                     "Failed while adding driver to linker".to_string(),
                 )
             })?;
-        // wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|e| {
-        //     component::module::component::units::driver::DriverError::SystemError(e.to_string())
-        // })?;
-
-        // wasmtime_wasi_http::add_to_linker_sync(&mut linker).map_err(|e| {
-        //     component::module::component::units::driver::DriverError::SystemError(e.to_string())
-        // })?;
+        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|e| {
+            component::module::component::units::driver::DriverError::SystemError(e.to_string())
+        })?;
+        wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker).map_err(|e| {
+            component::module::component::units::driver::DriverError::SystemError(e.to_string())
+        })?;
 
         Ok((linker, state))
     }
 
+    /// Resource table of the execution, holding its descriptors.
+    pub fn table(&mut self) -> &mut ResourceTable {
+        self.table.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Key the string based calls reach the descriptor of `resource` by.
+    pub fn descriptor_key(
+        &mut self,
+        resource: &Resource<Descriptor>,
+    ) -> Result<String, component::module::component::units::driver::DriverError> {
+        match self.table().get(resource) {
+            Ok(descriptor) if !descriptor.closed => {
+                Ok(format!("{}.{}", resource.rep(), descriptor.serial))
+            }
+            _ => Err(Descriptor::not_found()),
+        }
+    }
+
     pub fn get_descriptor(
-        &self,
+        &mut self,
         key: String,
-    ) -> Result<&Descriptor, component::module::component::units::driver::DriverError> {
-        self.descriptors.get(&key).ok_or(
-            component::module::component::units::driver::DriverError::InvalidInput(
-                "Failed while finding descriptor".to_string(),
-            ),
-        )
+    ) -> Result<Descriptor, component::module::component::units::driver::DriverError> {
+        Descriptor::find(self.table(), &key).cloned()
     }
 
     pub fn delete_descriptor(
         &mut self,
         key: String,
     ) -> Result<(), component::module::component::units::driver::DriverError> {
-        self.descriptors.remove(&key).ok_or(
-            component::module::component::units::driver::DriverError::InvalidInput(
-                "Failed while deleting descriptor".to_string(),
-            ),
-        )?;
-        Ok(())
+        Descriptor::remove(self.table(), &key)
     }
 
     pub async fn perform_bind(
@@ -267,53 +1116,32 @@ impl ProcessState {
             );
         }
 
-        let output = self.driver_runtime.resolver.get(path.as_str()).await;
-
-        match output {
-            None => {
-                let driver = self
-                    .get_driver(&driver_info, self.driver_runtime.engine.clone())
-                    .await?;
-                let (mut linker, mut state) = self.get_lower_runtime(driver_info.clone())?;
-
-                wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-                    component::module::component::units::driver::DriverError::SystemError(
-                        err.to_string(),
-                    )
-                })?;
-                let instance =
-                    component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                        .await
-                        .map_err(|e| {
-                            component::module::component::units::driver::DriverError::SystemError(
-                                e.to_string(),
-                            )
-                        })?;
-                let output = instance
-                    .component_units_driver()
-                    .call_bind(state, &input, None)
-                    .await
-                    .map_err(|e| {
-                        component::module::component::units::driver::DriverError::SystemError(
-                            e.to_string(),
-                        )
-                    })?
-                    .map_err(|e| {
-                        component::module::component::units::driver::DriverError::SystemError(
-                            e.to_string(),
-                        )
-                    })?;
-                let path_info = PathInfo {
-                    driver_name: driver_info.name,
-                    driver_version: driver_info.version,
-                    account_info: output,
-                };
-
-                self.driver_runtime
-                    .resolver
-                    .insert(path.clone(), path_info)
-                    .await;
-            }
+        // the input is checked before the driver sees it, against what its manifest declares
+        let manifest = self
+            .driver_runtime
+            .drivers
+            .manifest(&driver_info)
+            .await
+            .map_err(|e| {
+                component::module::component::units::driver::DriverError::SystemError(e.to_string())
+            })?
+            .unwrap_or_default();
+        if !manifest.supports("bind") {
+            return Err(
+                component::module::component::units::driver::DriverError::InvalidInput(
+                    "Driver does not support bind".to_string(),
+                ),
+            );
+        }
+        manifest.validate_bind_input(&input).map_err(|e| {
+            component::module::component::units::driver::DriverError::InvalidInput(format!(
+                "Invalid bind input: {}",
+                e
+            ))
+        })?;
+
+        let existing = match self.driver_runtime.resolver.get(path.as_str()).await {
+            None => None,
             Some(existing) => {
                 if existing.driver_name != driver_info.name
                     || existing.driver_version != driver_info.version
@@ -324,53 +1152,38 @@ impl ProcessState {
                         ),
                     );
                 }
-
-                let driver = self
-                    .get_driver(&driver_info, self.driver_runtime.engine.clone())
-                    .await?;
-                let (mut linker, mut state) = self.get_lower_runtime(driver_info.clone())?;
-
-                wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|err| {
-                    component::module::component::units::driver::DriverError::SystemError(
-                        err.to_string(),
-                    )
-                })?;
-                let instance =
-                    component::driver::DriverWorld::instantiate_async(&mut state, &driver, &linker)
-                        .await
-                        .map_err(|e| {
-                            component::module::component::units::driver::DriverError::SystemError(
-                                e.to_string(),
-                            )
-                        })?;
-
-                let output = instance
-                    .component_units_driver()
-                    .call_bind(state, &input, Some(&existing.account_info))
-                    .await
-                    .map_err(|_| {
-                        component::module::component::units::driver::DriverError::SystemError(
-                            "Failed while calling bind".to_string(),
-                        )
-                    })?
-                    .map_err(|_| {
-                        component::module::component::units::driver::DriverError::SystemError(
-                            "Failed while calling bind".to_string(),
-                        )
-                    })?;
-
-                let path_info = PathInfo {
-                    driver_name: driver_info.name,
-                    driver_version: driver_info.version,
-                    account_info: output,
-                };
-
-                self.driver_runtime
-                    .resolver
-                    .insert(path.clone(), path_info)
-                    .await;
+                Some(existing.account_info)
             }
+        };
+
+        let call = DriverCall::new(driver_info.clone(), Some(path.clone()), "bind");
+        let (instance, state) = self.instantiate_driver(&call).await?;
+        let result = with_deadline(
+            self.driver_runtime.driver_timeout(),
+            instance
+                .component_units_driver()
+                .call_bind(state, &input, existing.as_deref()),
+        )
+        .await;
+        let output = call.check(result).map_err(|failure| self.fail(*failure))?;
+        if let Err(e) = manifest.validate_account_info(&output) {
+            return Err(self.fail(call.failure(
+                FailureKind::SystemError,
+                format!("Driver returned invalid account info: {}", e),
+            )));
         }
+
+        let path_info = PathInfo {
+            driver_name: driver_info.name,
+            driver_version: driver_info.version,
+            account_info: output,
+        };
+
+        self.driver_runtime
+            .resolver
+            .insert(path.clone(), path_info)
+            .await;
+
         Ok(())
     }
 }
@@ -382,40 +1195,164 @@ impl DriverState {
         platform: Platform,
         event_sender: Arc<mpsc::Sender<Event>>,
     ) -> Self {
+        let wasi_ctx = guest_wasi_ctx(&driver_ctx.clock, &mut driver_ctx.random.clone());
         Self {
             ctx,
             driver_ctx,
             platform,
             event_sender,
-            // table: ResourceTable::new(),
-            // wasi_ctx: wasmtime_wasi::WasiCtxBuilder::new().build(),
+            table: Mutex::new(ResourceTable::new()),
+            wasi_ctx: Mutex::new(wasi_ctx),
+            http_ctx: WasiHttpCtx::new(),
         }
     }
 }
 
+/// `wasi:clocks` and `wasi:random` of a guest, backed by the same clock and randomness as the
+/// `clock` and `random` interfaces.
+fn guest_wasi_ctx(clock: &Clock, random: &mut Random) -> WasiCtx {
+    use rand::Rng;
+
+    wasmtime_wasi::WasiCtxBuilder::new()
+        .wall_clock(clock.clone())
+        .monotonic_clock(clock.clone())
+        .secure_random(random.clone())
+        .insecure_random(random.clone())
+        .insecure_random_seed(random.gen())
+        .build()
+}
+
 impl wasmtime_wasi::WasiView for DriverState {
     fn table(&mut self) -> &mut wasmtime_wasi::ResourceTable {
-        let resource = Box::new(wasmtime_wasi::ResourceTable::new());
-
-        Box::leak(resource)
+        self.table.get_mut().unwrap_or_else(|e| e.into_inner())
     }
 
     fn ctx(&mut self) -> &mut wasmtime_wasi::WasiCtx {
-        let ctx = Box::new(wasmtime_wasi::WasiCtx::builder().build());
-        Box::leak(ctx)
+        self.wasi_ctx.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// `wasi:http` for drivers, requests go through the same allowlist and limits as the
+/// `component:units/http` interfaces.
+impl WasiHttpView for DriverState {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.http_ctx
+    }
+
+    fn table(&mut self) -> &mut ResourceTable {
+        self.table.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<wasmtime_wasi_http::body::HyperOutgoingBody>,
+        mut config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let driver_info = &self.driver_ctx.driver_info;
+        let scheme = if config.use_tls { "https" } else { "http" };
+        let url = request.uri().authority().and_then(|authority| {
+            reqwest::Url::parse(&format!("{}://{}/", scheme, authority)).ok()
+        });
+
+        let allowed = url
+            .as_ref()
+            .is_some_and(|url| self.driver_ctx.sandbox.http.allows(url));
+        tracing::info!(
+            runtime = "driver",
+            call = "wasi-http",
+            driver = driver_info.name.as_str(),
+            version = driver_info.version.as_str(),
+            uri = %request.uri(),
+            allowed,
+            "http request"
+        );
+        if !allowed {
+            return Err(ErrorCode::HttpRequestDenied.into());
+        }
+
+        let timeout = self.driver_ctx.sandbox.http_limits.timeout;
+        config.connect_timeout = config.connect_timeout.min(timeout);
+        config.first_byte_timeout = config.first_byte_timeout.min(timeout);
+        config.between_bytes_timeout = config.between_bytes_timeout.min(timeout);
+
+        let max_response_size = self.driver_ctx.sandbox.http_limits.max_response_size;
+        let handle = wasmtime_wasi::runtime::spawn(async move {
+            let response = default_send_request_handler(request, config)
+                .await
+                .and_then(|mut response| {
+                    let too_large = ErrorCode::HttpResponseBodySize(Some(max_response_size as u64));
+                    if hyper::body::Body::size_hint(response.resp.body())
+                        .exact()
+                        .is_some_and(|length| length > max_response_size as u64)
+                    {
+                        return Err(too_large);
+                    }
+                    response.resp = response.resp.map(|body| {
+                        HyperIncomingBody::new(LimitedBody {
+                            inner: body,
+                            remaining: max_response_size,
+                            too_large,
+                        })
+                    });
+                    Ok(response)
+                });
+            Ok(response)
+        });
+
+        Ok(HostFutureIncomingResponse::pending(handle))
+    }
+}
+
+/// Body of a `wasi:http` response that fails once more than `remaining` bytes arrived, the
+/// length the server announced can be missing or wrong.
+struct LimitedBody {
+    inner: HyperIncomingBody,
+    remaining: usize,
+    too_large: ErrorCode,
+}
+
+impl hyper::body::Body for LimitedBody {
+    type Data = hyper::body::Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        use std::task::Poll;
+
+        let this = &mut *self;
+        match std::pin::Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    if data.len() > this.remaining {
+                        this.remaining = 0;
+                        return Poll::Ready(Some(Err(this.too_large.clone())));
+                    }
+                    this.remaining -= data.len();
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            other => other,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
     }
 }
 
 impl wasmtime_wasi::WasiView for ProcessState {
     fn table(&mut self) -> &mut wasmtime_wasi::ResourceTable {
-        let resource = Box::new(wasmtime_wasi::ResourceTable::new());
-
-        Box::leak(resource)
+        self.table.get_mut().unwrap_or_else(|e| e.into_inner())
     }
 
     fn ctx(&mut self) -> &mut wasmtime_wasi::WasiCtx {
-        let ctx = Box::new(wasmtime_wasi::WasiCtx::builder().build());
-        Box::leak(ctx)
+        self.wasi_ctx.get_mut().unwrap_or_else(|e| e.into_inner())
     }
 }
 
@@ -449,4 +1386,86 @@ mod tests {
         check_sync::<DriverState>(PhantomData);
         check_send::<DriverState>(PhantomData);
     }
+
+    #[test]
+    fn test_failure_marker() {
+        let failure = DriverFailure {
+            driver_info: DriverInfo {
+                name: "driver".to_string(),
+                version: "1.0.0".to_string(),
+            },
+            path: Some("~/alice".to_string()),
+            operation: "transfer-v2",
+            kind: FailureKind::InvalidInput,
+            message: "amount \"-1\" is negative".to_string(),
+            id: Some("V1StGXR8_Z5jdH".to_string()),
+        };
+        let marker = failure.marker().unwrap();
+        let error = failure.to_driver_error();
+
+        assert!(format!("{:?}", error).contains(&marker));
+        assert!(format!("Transfer failed: {:?}", error).contains(&marker));
+        // the text of the failure alone doesn't carry it
+        assert!(!failure.to_string().contains(&marker));
+    }
+
+    #[test]
+    fn test_descriptor_keys() {
+        let descriptor = |serial: u64, handle: bool| Descriptor {
+            path: format!("~/{}", serial),
+            driver_name: "driver".to_string(),
+            driver_version: "1.0.0".to_string(),
+            account_info: serde_json::Value::Null,
+            serial,
+            handle,
+            closed: false,
+        };
+        let mut table = ResourceTable::new();
+
+        let first = table.push(descriptor(0, false)).unwrap();
+        let first = format!("{}.0", first.rep());
+        Descriptor::remove(&mut table, &first).unwrap();
+        // the next descriptor can take the slot, not the key
+        let second = table.push(descriptor(1, false)).unwrap();
+        assert!(Descriptor::find(&table, &first).is_err());
+        assert!(Descriptor::remove(&mut table, &first).is_err());
+        assert_eq!(
+            Descriptor::find(&table, &format!("{}.1", second.rep()))
+                .unwrap()
+                .path,
+            "~/1"
+        );
+
+        // descriptors with a handle are closed, their entry goes when the handle is dropped
+        let third = table.push(descriptor(2, true)).unwrap();
+        let key = format!("{}.2", third.rep());
+        Descriptor::remove(&mut table, &key).unwrap();
+        assert!(Descriptor::find(&table, &key).is_err());
+        assert!(table.get(&third).unwrap().closed);
+        assert!(Descriptor::find(&table, "2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        use component::driver::exports::component::units::driver::DriverError;
+
+        let call = DriverCall::new(
+            DriverInfo {
+                name: "driver".to_string(),
+                version: "1.0.0".to_string(),
+            },
+            None,
+            "view",
+        );
+        let result: wasmtime::Result<Result<(), DriverError>> =
+            with_deadline(std::time::Duration::from_millis(1), std::future::pending()).await;
+        let failure = call.check(result).unwrap_err();
+
+        assert_eq!(failure.kind, FailureKind::DeadlineExceeded);
+        // reported with the driver and operation like the other failures
+        let error = crate::Error::from(*failure);
+        assert_eq!(error.code(), tonic::Code::DeadlineExceeded);
+        assert_eq!(error.reason(), "DEADLINE_EXCEEDED");
+        assert!(matches!(error, crate::Error::Driver(_)));
+    }
 }
@@ -0,0 +1,323 @@
+use axum::body::Bytes;
+use axum::extract::{Path, RawQuery, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::driver::DriverInfo;
+use super::types::component::callback::exports::component::units::callback::{
+    CallbackRequest, CallbackResponse,
+};
+use super::types::component::callback::CallbackDriverWorld;
+use super::types::component::module::component::units::driver::DriverError;
+use super::types::{with_deadline, DriverCall, FailureKind, ProcessState, UserCtx};
+use super::Runtime;
+
+/// Export of the drivers that receive callbacks.
+const CALLBACK_EXPORT: &str = "component:units/callback";
+
+/// User callbacks run as, they come from the systems drivers talk to and not from a user.
+const WEBHOOK_USER: &str = "webhook";
+
+/// Routes delivering `/hooks/{driver}/{version}/{path}` to the `on-callback` export of the driver.
+pub fn router(runtime: Runtime) -> axum::Router {
+    axum::Router::new()
+        .route("/hooks/:driver/:version", axum::routing::any(hook))
+        .route(
+            "/hooks/:driver/:version/*path",
+            axum::routing::any(hook_path),
+        )
+        .with_state(runtime)
+}
+
+async fn hook(
+    State(runtime): State<Runtime>,
+    Path((name, version)): Path<(String, String)>,
+    method: Method,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let driver_info = DriverInfo { name, version };
+    deliver(
+        runtime,
+        driver_info,
+        String::new(),
+        method,
+        query,
+        headers,
+        body,
+    )
+    .await
+}
+
+async fn hook_path(
+    State(runtime): State<Runtime>,
+    Path((name, version, path)): Path<(String, String, String)>,
+    method: Method,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let driver_info = DriverInfo { name, version };
+    deliver(runtime, driver_info, path, method, query, headers, body).await
+}
+
+async fn deliver(
+    runtime: Runtime,
+    driver_info: DriverInfo,
+    path: String,
+    method: Method,
+    query: Option<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    tracing::info!(
+        runtime = "webhook",
+        driver = driver_info.name.as_str(),
+        version = driver_info.version.as_str(),
+        path = path.as_str(),
+        %method,
+        "callback received"
+    );
+
+    let request = CallbackRequest {
+        method: method.to_string(),
+        path,
+        query,
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect(),
+        body: body.to_vec(),
+    };
+
+    match handle(&runtime, driver_info, &headers, request).await {
+        Ok(response) => into_response(response),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn handle(
+    runtime: &Runtime,
+    driver_info: DriverInfo,
+    headers: &HeaderMap,
+    request: CallbackRequest,
+) -> crate::Result<CallbackResponse> {
+    let webhook = runtime
+        .driver_layer
+        .config
+        .webhooks
+        .get(&driver_info.name)
+        .ok_or_else(|| crate::Error::not_found("webhook", driver_info.name.clone()))?;
+
+    if webhook.signature_header.is_none() && !webhook.insecure {
+        return Err(crate::Error::PermissionDenied(format!(
+            "Webhook of {} has no signature_header and isn't marked insecure",
+            driver_info.name
+        )));
+    }
+    if let Some(header) = &webhook.signature_header {
+        let secret = runtime
+            .driver_layer
+            .config_reader(&driver_info)
+            .get(&webhook.secret)
+            .await?
+            .ok_or_else(|| {
+                crate::Error::PermissionDenied(format!(
+                    "No webhook secret is set for {}",
+                    driver_info.name
+                ))
+            })?;
+        let signature = headers
+            .get(header)
+            .and_then(|signature| signature.to_str().ok())
+            .unwrap_or_default();
+        if !verify(&secret, &request.body, signature) {
+            tracing::warn!(
+                runtime = "webhook",
+                driver = driver_info.name.as_str(),
+                "callback signature mismatch"
+            );
+            return Err(crate::Error::Unauthenticated(
+                "Invalid callback signature".to_string(),
+            ));
+        }
+    }
+
+    let mut state = ProcessState::new(
+        UserCtx::new(WEBHOOK_USER),
+        runtime.driver_layer.clone(),
+        runtime.platform_layer.clone(),
+        runtime.event_sender.clone(),
+    );
+    let call = DriverCall::new(driver_info, None, "on-callback");
+
+    match on_callback(&mut state, &call, request).await {
+        Ok(response) => Ok(response),
+        Err(error) => Err(state
+            .failure_of(&error)
+            .map(crate::Error::from)
+            .unwrap_or_else(|| error.into())),
+    }
+}
+
+async fn on_callback(
+    state: &mut ProcessState,
+    call: &DriverCall,
+    request: CallbackRequest,
+) -> Result<CallbackResponse, DriverError> {
+    state.admit(call)?;
+
+    let engine = state.driver_runtime.engine.clone();
+    let driver = match state.get_driver(&call.driver_info, engine.clone()).await {
+        Ok(driver) => driver,
+        Err(_) => {
+            return Err(state.fail(call.failure(FailureKind::InvalidInput, "Driver not found")))
+        }
+    };
+    let manifest = match state
+        .driver_runtime
+        .drivers
+        .manifest(&call.driver_info)
+        .await
+    {
+        Ok(manifest) => manifest.unwrap_or_default(),
+        Err(e) => {
+            return Err(state.fail(call.failure(
+                FailureKind::SystemError,
+                format!("Failed while reading manifest: {:#}", e),
+            )))
+        }
+    };
+    if driver
+        .component_type()
+        .get_export(&engine, CALLBACK_EXPORT)
+        .is_none()
+        || !manifest.supports(call.operation)
+    {
+        return Err(state.fail(call.failure(
+            FailureKind::InvalidInput,
+            "Driver doesn't receive callbacks",
+        )));
+    }
+
+    let sandbox = match state.driver_runtime.sandbox(&call.driver_info).await {
+        Ok(sandbox) => sandbox,
+        Err(e) => {
+            return Err(state.fail(call.failure(
+                FailureKind::SystemError,
+                format!("Failed while building sandbox: {:#}", e),
+            )))
+        }
+    };
+    let (linker, mut store) = state.get_lower_runtime(call, sandbox)?;
+
+    let instance = match CallbackDriverWorld::instantiate_async(&mut store, &driver, &linker).await
+    {
+        Ok(instance) => instance,
+        Err(e) => {
+            return Err(state.fail(call.failure(
+                FailureKind::Trap,
+                format!("Failed while instantiating driver: {:#}", e),
+            )))
+        }
+    };
+
+    let result = with_deadline(
+        state.driver_runtime.driver_timeout(),
+        instance
+            .component_units_callback()
+            .call_on_callback(&mut store, &request),
+    )
+    .await;
+    call.check(result).map_err(|failure| state.fail(*failure))
+}
+
+/// Checks `signature`, the hex encoded HMAC-SHA256 of `body`, optionally prefixed with `sha256=`.
+fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn into_response(response: CallbackResponse) -> Response {
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::BAD_GATEWAY);
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in response.headers {
+        match (
+            HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.append(name, value);
+            }
+            _ => tracing::warn!(
+                runtime = "webhook",
+                header = name.as_str(),
+                "dropping invalid response header"
+            ),
+        }
+    }
+
+    (status, headers, response.body).into_response()
+}
+
+fn error_response(error: crate::Error) -> Response {
+    let status = match error.code() {
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        tonic::Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+        tonic::Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        tonic::Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    // internal errors stay in the logs, the caller is another system and not an admin
+    let message = match &error {
+        crate::Error::Internal(e) => {
+            tracing::error!(runtime = "webhook", error = ?e, "Internal error");
+            "Internal error".to_string()
+        }
+        _ => error.to_string(),
+    };
+
+    (status, message).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"{\"status\":\"SUCCESS\"}");
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        assert!(verify("secret", b"{\"status\":\"SUCCESS\"}", &signature));
+        assert!(verify(
+            "secret",
+            b"{\"status\":\"SUCCESS\"}",
+            &format!("sha256={}", signature)
+        ));
+        assert!(!verify("secret", b"{\"status\":\"FAILURE\"}", &signature));
+        assert!(!verify("other", b"{\"status\":\"SUCCESS\"}", &signature));
+        assert!(!verify("secret", b"{\"status\":\"SUCCESS\"}", "not hex"));
+        assert!(!verify("secret", b"{\"status\":\"SUCCESS\"}", ""));
+    }
+}
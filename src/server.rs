@@ -69,14 +69,24 @@ impl Server {
         );
         let bind_service =
             super::service::proto_types::bind_server::BindServer::new(self.runtime.clone());
+        // the binary is checked against the configured limit by the service, the message
+        // carrying it only needs to get there
         let driver_service =
-            super::service::proto_types::driver_server::DriverServer::new(self.runtime.clone());
+            super::service::proto_types::driver_server::DriverServer::new(self.runtime.clone())
+                .max_decoding_message_size(
+                    self.runtime.driver_layer.config.max_component_size + 64 * 1024,
+                );
 
         let driver_details_service =
             super::service::proto_types::driver_details_server::DriverDetailsServer::new(
                 self.runtime.clone(),
             );
 
+        let settlement_service =
+            super::service::proto_types::settlement_server::SettlementServer::new(
+                self.runtime.clone(),
+            );
+
         let user_sign_up_service =
             super::service::proto_types::user_sign_up_server::UserSignUpServer::new(
                 self.runtime.clone(),
@@ -92,16 +102,21 @@ impl Server {
                 self.runtime.clone(),
             );
 
+        // plain http routes served next to the gRPC services, for callbacks to drivers
+        let webhook_routes = runtime_v2::webhook::router(self.runtime.clone());
+
         tonic::transport::Server::builder()
             .accept_http1(true)
             .layer(CorsLayer::permissive()) // Handle CORS
             .layer(GrpcWebLayer::new())
+            .add_routes(webhook_routes.into())
             .add_service(reflection_service)
             .add_service(health_service)
             .add_service(execution_service)
             .add_service(bind_service)
             .add_service(driver_service)
             .add_service(driver_details_service)
+            .add_service(settlement_service)
             .add_service(user_sign_up_service)
             .add_service(user_login_sevice)
             .add_service(user_check_server)